@@ -19,15 +19,32 @@ LAUNCHD_DOMAIN="gui/${USER_ID}"
 REMOUNTER_HOST="${REMOUNTER_HOST:-}"
 REMOUNTER_SHARES="${REMOUNTER_SHARES:-}"
 REMOUNTER_POST_MOUNT_SCRIPT="${REMOUNTER_POST_MOUNT_SCRIPT:-}"
+REMOUNTER_CONFIG="${REMOUNTER_CONFIG:-}"
+REMOUNTER_LOG_FORMAT="${REMOUNTER_LOG_FORMAT:-text}"
+REMOUNTER_LOG_LEVEL="${REMOUNTER_LOG_LEVEL:-}"
+REMOUNTER_LOG_MAX_SIZE="${REMOUNTER_LOG_MAX_SIZE:-10MB}"
+REMOUNTER_LOG_KEEP="${REMOUNTER_LOG_KEEP:-5}"
 
 usage() {
     cat <<EOF
-Usage: $(basename "$0") --host <hostname> --shares <share1,share2> [--post-mount-script <path>]
+Usage: $(basename "$0") --host <hostname> --shares <share1,share2> [--post-mount-script <path>] [log options]
+       $(basename "$0") --config <path> [log options]
+
+Log options:
+  --log-format <text|json>    Format of the log file (default: text)
+  --log-level <level>         Which messages to log, e.g. info (default: debug)
+  --log-max-size <size>       Rotate the log file at this size (default: 10MB)
+  --log-keep <count>          Rotated log files to keep (default: 5)
 
 Environment variables (used when flags are omitted):
   REMOUNTER_HOST              SMB host to monitor
   REMOUNTER_SHARES            Comma-separated share names
   REMOUNTER_POST_MOUNT_SCRIPT Optional script to run after remounting
+  REMOUNTER_CONFIG            TOML configuration file (instead of host and shares)
+  REMOUNTER_LOG_FORMAT        Format of the log file
+  REMOUNTER_LOG_LEVEL         Which messages to log
+  REMOUNTER_LOG_MAX_SIZE      Rotate the log file at this size
+  REMOUNTER_LOG_KEEP          Rotated log files to keep
 
 Example:
   $(basename "$0") --host nas.local --shares Media,home
@@ -48,6 +65,26 @@ while [[ $# -gt 0 ]]; do
             REMOUNTER_POST_MOUNT_SCRIPT="${2:-}"
             shift 2
             ;;
+        --config)
+            REMOUNTER_CONFIG="${2:-}"
+            shift 2
+            ;;
+        --log-format)
+            REMOUNTER_LOG_FORMAT="${2:-}"
+            shift 2
+            ;;
+        --log-level)
+            REMOUNTER_LOG_LEVEL="${2:-}"
+            shift 2
+            ;;
+        --log-max-size)
+            REMOUNTER_LOG_MAX_SIZE="${2:-}"
+            shift 2
+            ;;
+        --log-keep)
+            REMOUNTER_LOG_KEEP="${2:-}"
+            shift 2
+            ;;
         -h | --help)
             usage
             exit 0
@@ -60,16 +97,34 @@ while [[ $# -gt 0 ]]; do
     esac
 done
 
-if [[ -z "${REMOUNTER_HOST}" || -z "${REMOUNTER_SHARES}" ]]; then
-    echo "Error: --host and --shares are required." >&2
+if [[ -n "${REMOUNTER_CONFIG}" ]]; then
+    if [[ ! -f "${REMOUNTER_CONFIG}" ]]; then
+        echo "Error: config file ${REMOUNTER_CONFIG} does not exist." >&2
+        exit 1
+    fi
+    REMOUNTER_CONFIG="$(cd "$(dirname "${REMOUNTER_CONFIG}")" && pwd)/$(basename "${REMOUNTER_CONFIG}")"
+elif [[ -z "${REMOUNTER_HOST}" || -z "${REMOUNTER_SHARES}" ]]; then
+    echo "Error: --host and --shares (or --config) are required." >&2
     usage >&2
     exit 1
 fi
 
 write_plist() {
-    local args=("${INSTALL_BIN}" "${REMOUNTER_HOST}" "${REMOUNTER_SHARES}")
-    if [[ -n "${REMOUNTER_POST_MOUNT_SCRIPT}" ]]; then
-        args+=(--post-mount-script "${REMOUNTER_POST_MOUNT_SCRIPT}")
+    local args
+    if [[ -n "${REMOUNTER_CONFIG}" ]]; then
+        args=("${INSTALL_BIN}" --config "${REMOUNTER_CONFIG}")
+    else
+        args=("${INSTALL_BIN}" "${REMOUNTER_HOST}" "${REMOUNTER_SHARES}")
+        if [[ -n "${REMOUNTER_POST_MOUNT_SCRIPT}" ]]; then
+            args+=(--post-mount-script "${REMOUNTER_POST_MOUNT_SCRIPT}")
+        fi
+    fi
+
+    # The daemon writes and rotates its own log file, launchd only catches crashes on stderr
+    args+=(--log-file "${LOG_OUT}" --log-format "${REMOUNTER_LOG_FORMAT}")
+    args+=(--log-max-size "${REMOUNTER_LOG_MAX_SIZE}" --log-keep "${REMOUNTER_LOG_KEEP}")
+    if [[ -n "${REMOUNTER_LOG_LEVEL}" ]]; then
+        args+=(--log-level "${REMOUNTER_LOG_LEVEL}")
     fi
 
     cat >"${PLIST_PATH}" <<EOF
@@ -98,9 +153,6 @@ $(printf '        <string>%s</string>\n' "${args[@]}")
     <key>ThrottleInterval</key>
     <integer>10</integer>
 
-    <key>StandardOutPath</key>
-    <string>${LOG_OUT}</string>
-
     <key>StandardErrorPath</key>
     <string>${LOG_ERR}</string>
 </dict>
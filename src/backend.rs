@@ -0,0 +1,195 @@
+mod applescript;
+mod cifs;
+#[cfg(test)]
+pub mod stub;
+
+use std::{
+    fmt::Debug,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::Path,
+    process,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing::warn;
+
+pub use applescript::AppleScriptBackend;
+pub use cifs::{CifsBackend, CifsOptions};
+
+use crate::{config::DEFAULT_PORT, mount_table, probe::HealthCheck};
+
+/// Why a mounted share is degraded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DegradedReason {
+    /// The share can be read but not written
+    ReadOnly,
+    /// Writing to the share failed or did not read back correctly
+    WriteFailed,
+}
+
+/// Health of a share as seen by a mount backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareHealth {
+    /// The share is mounted and the marker file can be read
+    Healthy,
+    /// The share path exists but the marker file or directory cannot be read
+    Unhealthy,
+    /// The marker file does not contain the expected content, so this is the wrong share
+    MarkerMismatch,
+    /// The share is mounted and readable but the write probe failed
+    Degraded(DegradedReason),
+    /// The share is not mounted
+    NotMounted,
+}
+
+/// A mechanism for mounting and unmounting SMB shares
+pub trait MountBackend: Debug + Send + Sync {
+    /// The directory under which shares are mounted by default
+    fn mount_root(&self) -> &Path;
+
+    /// Mount `//server:port/share` at the given mount point
+    fn mount(&self, server: &str, port: u16, share: &str, mount_point: &Path) -> Result<()>;
+
+    /// Unmount the share mounted at the given mount point
+    ///
+    /// A forced unmount detaches the share even if it is busy or unresponsive
+    fn unmount(&self, mount_point: &Path, force: bool) -> Result<()>;
+
+    /// Check whether something is mounted at the given mount point
+    ///
+    /// The system mount table is consulted rather than the directory itself,
+    /// since mount point directories can be left behind after unmounting
+    fn is_mounted(&self, mount_point: &Path) -> bool {
+        match mount_table::find(mount_point) {
+            Ok(entry) => entry.is_some(),
+            Err(e) => {
+                warn!("Could not read the mount table: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Check the health of the share mounted at the given mount point
+    ///
+    /// This touches the mounted filesystem so it may block on a hung mount
+    fn health(&self, mount_point: &Path, check: &HealthCheck) -> ShareHealth {
+        // A share that is not mounted cannot be healthy
+        if !self.is_mounted(mount_point) {
+            return ShareHealth::NotMounted;
+        }
+
+        // The share is only healthy if the marker file can be read
+        let Ok(marker) = fs::read(mount_point.join(&check.marker)) else {
+            return ShareHealth::Unhealthy;
+        };
+
+        // If the marker has expected content, make sure this is the right share
+        if let Some(expected) = &check.marker_content
+            && String::from_utf8_lossy(&marker).trim() != expected.trim()
+        {
+            return ShareHealth::MarkerMismatch;
+        }
+
+        // Optionally make sure the directory can be listed too
+        if check.list_directory
+            && fs::read_dir(mount_point).map_or(true, |mut entries| {
+                entries.next().is_some_and(|entry| entry.is_err())
+            })
+        {
+            return ShareHealth::Unhealthy;
+        }
+
+        // Optionally make sure the share can be written to
+        if check.write_probe
+            && let Err(reason) = write_probe(mount_point)
+        {
+            return ShareHealth::Degraded(reason);
+        }
+
+        ShareHealth::Healthy
+    }
+}
+
+/// Create, sync, read back and delete a temporary file in the root of the share
+fn write_probe(mount_point: &Path) -> Result<(), DegradedReason> {
+    // Use a unique name so concurrent probes never collide
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos());
+    let path = mount_point.join(format!(".remounter-probe-{}-{}", process::id(), nanos));
+    let contents = format!("remounter write probe {}", nanos);
+
+    // Write the file and make sure it reaches the server
+    let written = File::create_new(&path).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&path);
+        return Err(match e.kind() {
+            ErrorKind::ReadOnlyFilesystem | ErrorKind::PermissionDenied => DegradedReason::ReadOnly,
+            _ => DegradedReason::WriteFailed,
+        });
+    }
+
+    // Read the file back, then delete it
+    let read_back = fs::read(&path);
+    let removed = fs::remove_file(&path);
+    match read_back {
+        Ok(read_back) if read_back == contents.as_bytes() && removed.is_ok() => Ok(()),
+        _ => Err(DegradedReason::WriteFailed),
+    }
+}
+
+/// Format the `server[:port]` part of an SMB URL, omitting the default port
+fn server_authority(server: &str, port: u16) -> String {
+    if port == DEFAULT_PORT {
+        server.to_string()
+    } else {
+        format!("{}:{}", server, port)
+    }
+}
+
+/// The available mount backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// Mount through Finder using `osascript` (macOS)
+    #[value(name = "applescript")]
+    AppleScript,
+    /// Mount with the kernel CIFS client using `mount.cifs` (Linux)
+    Cifs,
+}
+
+impl Default for BackendKind {
+    /// Use Finder on macOS and the kernel CIFS client everywhere else
+    fn default() -> Self {
+        if cfg!(target_os = "macos") {
+            BackendKind::AppleScript
+        } else {
+            BackendKind::Cifs
+        }
+    }
+}
+
+impl std::fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendKind::AppleScript => write!(f, "applescript"),
+            BackendKind::Cifs => write!(f, "cifs"),
+        }
+    }
+}
+
+/// Create a new mount backend of the given kind
+pub fn new_backend(kind: BackendKind, cifs_options: CifsOptions) -> Arc<dyn MountBackend> {
+    match kind {
+        BackendKind::AppleScript => Arc::new(AppleScriptBackend::new()),
+        BackendKind::Cifs => Arc::new(CifsBackend::new(cifs_options)),
+    }
+}
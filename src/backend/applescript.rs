@@ -0,0 +1,115 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Result;
+use tracing::{debug, instrument};
+
+use super::{MountBackend, server_authority};
+use crate::process;
+
+/// Mount backend that asks Finder to mount shares using AppleScript
+///
+/// Finder can only mount shares under /Volumes, so shares with a mount point
+/// elsewhere are mounted with `mount_smbfs` instead
+#[derive(Debug)]
+pub struct AppleScriptBackend {
+    mount_root: PathBuf,
+}
+
+impl AppleScriptBackend {
+    /// Create a new AppleScript backend, Finder always mounts shares under /Volumes
+    pub fn new() -> Self {
+        Self {
+            mount_root: PathBuf::from("/Volumes"),
+        }
+    }
+
+    /// Mount a share at a mount point outside /Volumes using `mount_smbfs`
+    fn mount_smbfs(&self, server: &str, port: u16, share: &str, mount_point: &Path) -> Result<()> {
+        // Make sure the mount point exists
+        fs::create_dir_all(mount_point)?;
+
+        // Construct the mount command
+        let mut command = Command::new("mount_smbfs");
+        command
+            .arg(format!("//{}/{}", server_authority(server, port), share))
+            .arg(mount_point);
+
+        // Log the mount command for info
+        debug!("Executing mount command: {:?}", command);
+
+        // Execute the mount command
+        let status = process::status(&mut command)?;
+
+        // Check if the command was successful, return an error if not
+        if !status.success() {
+            return Err(anyhow::anyhow!("Failed to execute mount command"));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for AppleScriptBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MountBackend for AppleScriptBackend {
+    fn mount_root(&self) -> &Path {
+        &self.mount_root
+    }
+
+    #[instrument(skip(self))]
+    fn mount(&self, server: &str, port: u16, share: &str, mount_point: &Path) -> Result<()> {
+        // Shares outside the Finder layout are mounted directly at their mount point
+        if mount_point != self.mount_root.join(share) {
+            return self.mount_smbfs(server, port, share, mount_point);
+        }
+
+        // Construct the mount command
+        let mount_command = format!(
+            "osascript -e 'mount volume \"smb://{}/{}\"'",
+            server_authority(server, port),
+            share
+        );
+
+        // Log the mount command for info
+        debug!("Executing mount command: {}", mount_command);
+
+        // Execute the mount command using AppleScript
+        let status = process::status(Command::new("sh").arg("-c").arg(mount_command))?;
+
+        // Check if the command was successful, return an error if not
+        if !status.success() {
+            return Err(anyhow::anyhow!("Failed to execute mount command"));
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn unmount(&self, mount_point: &Path, force: bool) -> Result<()> {
+        // Ask diskutil to unmount the volume
+        let mut command = Command::new("diskutil");
+        command.arg("unmount");
+        if force {
+            command.arg("force");
+        }
+        let status = process::status(command.arg(mount_point))?;
+
+        // Check if the command was successful, return an error if not
+        if !status.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to unmount {}",
+                mount_point.display()
+            ));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Result;
+use clap::Args;
+use serde::Deserialize;
+use tracing::{debug, instrument};
+
+use super::MountBackend;
+use crate::{config::DEFAULT_PORT, process};
+
+/// Options passed to `mount.cifs`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Args, Deserialize)]
+#[command(about = None, long_about = None)]
+#[serde(deny_unknown_fields)]
+pub struct CifsOptions {
+    /// SMB protocol version to request (e.g., 3.0)
+    #[arg(long = "cifs-vers")]
+    pub vers: Option<String>,
+
+    /// User ID that owns the files on the mounted share
+    #[arg(long = "cifs-uid")]
+    pub uid: Option<u32>,
+
+    /// Group ID that owns the files on the mounted share
+    #[arg(long = "cifs-gid")]
+    pub gid: Option<u32>,
+
+    /// Permissions applied to files on the mounted share (e.g., 0644)
+    #[arg(long = "cifs-file-mode")]
+    pub file_mode: Option<String>,
+
+    /// Credentials file containing the username and password
+    #[arg(long = "cifs-credentials")]
+    pub credentials: Option<PathBuf>,
+}
+
+impl CifsOptions {
+    /// Build the comma-separated option string passed to `mount.cifs -o`
+    fn to_option_string(&self) -> String {
+        let mut options = Vec::new();
+        if let Some(vers) = &self.vers {
+            options.push(format!("vers={}", vers));
+        }
+        if let Some(uid) = self.uid {
+            options.push(format!("uid={}", uid));
+        }
+        if let Some(gid) = self.gid {
+            options.push(format!("gid={}", gid));
+        }
+        if let Some(file_mode) = &self.file_mode {
+            options.push(format!("file_mode={}", file_mode));
+        }
+        if let Some(credentials) = &self.credentials {
+            options.push(format!("credentials={}", credentials.display()));
+        }
+        options.join(",")
+    }
+}
+
+/// Mount backend that mounts shares with the Linux CIFS client
+#[derive(Debug)]
+pub struct CifsBackend {
+    mount_root: PathBuf,
+    options: CifsOptions,
+}
+
+impl CifsBackend {
+    /// Create a new CIFS backend, shares are mounted under /mnt
+    pub fn new(options: CifsOptions) -> Self {
+        Self {
+            mount_root: PathBuf::from("/mnt"),
+            options,
+        }
+    }
+}
+
+impl MountBackend for CifsBackend {
+    fn mount_root(&self) -> &Path {
+        &self.mount_root
+    }
+
+    #[instrument(skip(self))]
+    fn mount(&self, server: &str, port: u16, share: &str, mount_point: &Path) -> Result<()> {
+        // Make sure the mount point exists
+        fs::create_dir_all(mount_point)?;
+
+        // Construct the mount command
+        let mut command = Command::new("mount.cifs");
+        command
+            .arg(format!("//{}/{}", server, share))
+            .arg(mount_point);
+        let mut options = self.options.to_option_string();
+        if port != DEFAULT_PORT {
+            if !options.is_empty() {
+                options.push(',');
+            }
+            options.push_str(&format!("port={}", port));
+        }
+        if !options.is_empty() {
+            command.arg("-o").arg(options);
+        }
+
+        // Log the mount command for info
+        debug!("Executing mount command: {:?}", command);
+
+        // Execute the mount command
+        let status = process::status(&mut command)?;
+
+        // Check if the command was successful, return an error if not
+        if !status.success() {
+            return Err(anyhow::anyhow!("Failed to execute mount command"));
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn unmount(&self, mount_point: &Path, force: bool) -> Result<()> {
+        // Unmount the share
+        let mut command = Command::new("umount");
+        if force {
+            command.arg("-f");
+        }
+        let mut status = process::status(command.arg(mount_point))?;
+
+        // A forced unmount can fail on an unresponsive server, fall back to a lazy unmount
+        if !status.success() && force {
+            debug!(
+                "Forced unmount of {} failed, trying a lazy unmount",
+                mount_point.display()
+            );
+            status = process::status(Command::new("umount").arg("-l").arg(mount_point))?;
+        }
+
+        // Check if the command was successful, return an error if not
+        if !status.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to unmount {}",
+                mount_point.display()
+            ));
+        }
+
+        Ok(())
+    }
+}
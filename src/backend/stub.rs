@@ -0,0 +1,65 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use anyhow::Result;
+
+use super::{MountBackend, ShareHealth};
+use crate::probe::HealthCheck;
+
+/// A backend that refuses a number of mounts before succeeding, without touching the system
+#[derive(Debug)]
+pub struct StubBackend {
+    root: PathBuf,
+    refusals: AtomicU32,
+    mounted: AtomicBool,
+}
+
+impl StubBackend {
+    /// Create a stub backend that refuses the given number of mounts first
+    pub fn new(refusals: u32) -> Self {
+        Self {
+            root: PathBuf::from("/nonexistent/remounter"),
+            refusals: AtomicU32::new(refusals),
+            mounted: AtomicBool::new(false),
+        }
+    }
+}
+
+impl MountBackend for StubBackend {
+    fn mount_root(&self) -> &Path {
+        &self.root
+    }
+
+    fn mount(&self, _server: &str, _port: u16, _share: &str, _mount_point: &Path) -> Result<()> {
+        if self
+            .refusals
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |refusals| {
+                refusals.checked_sub(1)
+            })
+            .is_ok()
+        {
+            return Err(anyhow::anyhow!("server refused the mount"));
+        }
+        self.mounted.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn unmount(&self, _mount_point: &Path, _force: bool) -> Result<()> {
+        self.mounted.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn is_mounted(&self, _mount_point: &Path) -> bool {
+        self.mounted.load(Ordering::SeqCst)
+    }
+
+    fn health(&self, mount_point: &Path, _check: &HealthCheck) -> ShareHealth {
+        if self.is_mounted(mount_point) {
+            ShareHealth::Healthy
+        } else {
+            ShareHealth::NotMounted
+        }
+    }
+}
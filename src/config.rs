@@ -0,0 +1,673 @@
+use std::{
+    collections::HashSet,
+    fs,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    backend::{BackendKind, CifsOptions},
+    probe::HealthCheck,
+    process::DEFAULT_SHUTDOWN_TIMEOUT,
+    retry::RetryPolicy,
+    server::ProbeKind,
+};
+
+/// The default SMB port
+pub const DEFAULT_PORT: u16 = 445;
+
+/// How long a reachability probe may take by default
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often servers are probed by default
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The longest poll interval allowed, so deadlines always stay representable
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The longest probe or shutdown timeout allowed
+const MAX_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Configuration for the whole daemon, usually loaded from a TOML file
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The default backend for servers that do not specify one
+    #[serde(default)]
+    pub backend: Option<BackendKind>,
+
+    /// The default directory shares are mounted under
+    #[serde(default)]
+    pub mount_root: Option<String>,
+
+    /// The default SMB port for servers that do not specify one
+    #[serde(default)]
+    pub port: Option<u16>,
+
+    /// How long a reachability probe may take before the server is considered down (e.g., "10s")
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub probe_timeout: Option<Duration>,
+
+    /// How often servers are probed (e.g., "30s")
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub poll_interval: Option<Duration>,
+
+    /// How long shutting down may take before running commands are killed (e.g., "10s")
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub shutdown_timeout: Option<Duration>,
+
+    /// Whether to unmount the shares the daemon mounted itself when it exits
+    #[serde(default)]
+    pub unmount_on_exit: Option<bool>,
+
+    /// The Unix socket the daemon listens on for control requests
+    #[serde(default)]
+    pub control_socket: Option<String>,
+
+    /// Where to serve Prometheus metrics, as a port on localhost or an address and port
+    #[serde(default)]
+    pub metrics_address: Option<String>,
+
+    /// The default reachability probe for servers that do not specify one
+    #[serde(default)]
+    pub probe: Option<ProbeKind>,
+
+    /// The default retry policy for every share
+    #[serde(default)]
+    pub retry: RetryConfig,
+
+    /// The default health check for every share
+    #[serde(default)]
+    pub health: HealthConfig,
+
+    /// The servers to monitor
+    #[serde(default, rename = "server")]
+    pub servers: Vec<ServerConfig>,
+}
+
+/// Configuration for a single server and its shares
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The hostname to monitor (e.g., nas.local)
+    pub host: String,
+
+    /// Other names or addresses of the same server, tried in order when the host is unreachable
+    #[serde(default)]
+    pub fallback_hosts: Vec<String>,
+
+    /// The SMB port on the server
+    #[serde(default)]
+    pub port: Option<u16>,
+
+    /// How long a reachability probe of this server may take (e.g., "10s")
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub probe_timeout: Option<Duration>,
+
+    /// How often this server is probed (e.g., "30s")
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub poll_interval: Option<Duration>,
+
+    /// The backend used to mount this server's shares
+    #[serde(default)]
+    pub backend: Option<BackendKind>,
+
+    /// The directory this server's shares are mounted under
+    #[serde(default)]
+    pub mount_root: Option<String>,
+
+    /// A script to run after remounting this server's shares
+    #[serde(default)]
+    pub post_mount_script: Option<String>,
+
+    /// Options used by the cifs backend
+    #[serde(default)]
+    pub cifs: CifsOptions,
+
+    /// How this server's reachability is checked
+    #[serde(default)]
+    pub probe: Option<ProbeKind>,
+
+    /// The retry policy for this server's shares
+    #[serde(default)]
+    pub retry: RetryConfig,
+
+    /// The health check for this server's shares
+    #[serde(default)]
+    pub health: HealthConfig,
+
+    /// The shares to remount
+    #[serde(default, rename = "share")]
+    pub shares: Vec<ShareConfig>,
+}
+
+/// Configuration for a single share
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShareConfig {
+    /// The name of the share on the server
+    pub name: String,
+
+    /// The local directory the share is mounted at
+    #[serde(default)]
+    pub mount_point: Option<String>,
+
+    /// The retry policy for this share
+    #[serde(default)]
+    pub retry: RetryConfig,
+
+    /// The health check for this share
+    #[serde(default)]
+    pub health: HealthConfig,
+}
+
+/// How failed mounts are retried, any unset values are inherited
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    /// The delay before the first retry (e.g., "5s")
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub initial_delay: Option<Duration>,
+
+    /// The factor the delay grows by after each failed attempt
+    #[serde(default)]
+    pub multiplier: Option<f64>,
+
+    /// The longest delay between attempts (e.g., "5m")
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub max_delay: Option<Duration>,
+
+    /// The number of attempts before giving up until the server reconnects
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+
+    /// The fraction of the delay to randomly add or subtract (0.0 to 1.0)
+    #[serde(default)]
+    pub jitter: Option<f64>,
+}
+
+/// How share health is checked, any unset values are inherited
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthConfig {
+    /// The marker file that must be readable, relative to the share root (e.g., ".smb_remounter")
+    #[serde(default)]
+    pub marker: Option<String>,
+
+    /// The content the marker file must contain, ignoring surrounding whitespace
+    #[serde(default)]
+    pub marker_content: Option<String>,
+
+    /// How long a health probe may take before the share is considered stale (e.g., "5s")
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+
+    /// Whether to list the root directory of the share as part of the probe
+    #[serde(default)]
+    pub list_directory: Option<bool>,
+
+    /// Whether to create, sync, read back and delete a file as part of the probe
+    #[serde(default)]
+    pub write_probe: Option<bool>,
+
+    /// Whether to force-unmount and remount a share that is stale
+    #[serde(default)]
+    pub recover_stale: Option<bool>,
+}
+
+/// Deserialize a human-readable duration such as "30s" or "5m"
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    humantime::parse_duration(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+impl Config {
+    /// Load and validate the configuration from a TOML file
+    pub fn load(path: &Path) -> Result<Self> {
+        // Read and parse the file
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Could not read config file {}", path.display()))?;
+        let config: Config = toml::from_str(&contents)
+            .with_context(|| format!("Could not parse config file {}", path.display()))?;
+
+        // Make sure the configuration makes sense before using it
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Check the configuration for missing or duplicated entries
+    pub fn validate(&self) -> Result<()> {
+        if self.servers.is_empty() {
+            return Err(anyhow::anyhow!("No servers configured"));
+        }
+        if self.shutdown_timeout().is_zero() {
+            return Err(anyhow::anyhow!(
+                "Shutdown timeout must be greater than zero"
+            ));
+        }
+        if self.shutdown_timeout() > MAX_TIMEOUT {
+            return Err(anyhow::anyhow!(
+                "Shutdown timeout must not be longer than {}",
+                humantime::format_duration(MAX_TIMEOUT)
+            ));
+        }
+        self.metrics_address()?;
+
+        let mut hosts = HashSet::new();
+        for server in &self.servers {
+            if server.host.trim().is_empty() {
+                return Err(anyhow::anyhow!("Server with an empty host"));
+            }
+            if server
+                .fallback_hosts
+                .iter()
+                .any(|host| host.trim().is_empty())
+            {
+                return Err(anyhow::anyhow!("Empty fallback host for {}", server.host));
+            }
+            let mut candidates = HashSet::new();
+            if let Some(host) = server
+                .candidate_hosts()
+                .iter()
+                .find(|host| !candidates.insert(host.as_str()))
+            {
+                return Err(anyhow::anyhow!(
+                    "Host {} is listed twice for {}",
+                    host,
+                    server.host
+                ));
+            }
+            if !hosts.insert(server.host.as_str()) {
+                return Err(anyhow::anyhow!(
+                    "Server {} is configured twice",
+                    server.host
+                ));
+            }
+            if server.port(self) == 0 {
+                return Err(anyhow::anyhow!("Invalid port 0 for {}", server.host));
+            }
+            if server.probe_timeout(self).is_zero() {
+                return Err(anyhow::anyhow!(
+                    "Probe timeout for {} must be greater than zero",
+                    server.host
+                ));
+            }
+            if server.probe_timeout(self) > MAX_TIMEOUT {
+                return Err(anyhow::anyhow!(
+                    "Probe timeout for {} must not be longer than {}",
+                    server.host,
+                    humantime::format_duration(MAX_TIMEOUT)
+                ));
+            }
+            if server.poll_interval(self).is_zero() {
+                return Err(anyhow::anyhow!(
+                    "Poll interval for {} must be greater than zero",
+                    server.host
+                ));
+            }
+            if server.poll_interval(self) > MAX_POLL_INTERVAL {
+                return Err(anyhow::anyhow!(
+                    "Poll interval for {} must not be longer than {}",
+                    server.host,
+                    humantime::format_duration(MAX_POLL_INTERVAL)
+                ));
+            }
+            if server.cifs.credentials.is_some() && server.backend_kind(self) != BackendKind::Cifs {
+                return Err(anyhow::anyhow!(
+                    "Credentials for {} are only used by the cifs backend",
+                    server.host
+                ));
+            }
+            if server.shares.is_empty() {
+                return Err(anyhow::anyhow!("No shares configured for {}", server.host));
+            }
+
+            let mut names = HashSet::new();
+            for share in &server.shares {
+                self.retry_policy(server, share)
+                    .validate()
+                    .with_context(|| {
+                        format!("Invalid retry policy for {} on {}", share.name, server.host)
+                    })?;
+                self.health_check(server, share)
+                    .validate()
+                    .with_context(|| {
+                        format!("Invalid health check for {} on {}", share.name, server.host)
+                    })?;
+                if share.name.trim().trim_start_matches('/').is_empty() {
+                    return Err(anyhow::anyhow!("Invalid share name on {}", server.host));
+                }
+                if !names.insert(share.name.as_str()) {
+                    return Err(anyhow::anyhow!(
+                        "Share {} is configured twice on {}",
+                        share.name,
+                        server.host
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    /// How long shutting down may take, falling back to the default
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT)
+    }
+
+    /// Whether to unmount the shares the daemon mounted itself when it exits
+    pub fn unmount_on_exit(&self) -> bool {
+        self.unmount_on_exit.unwrap_or(false)
+    }
+
+    /// Where to serve metrics, if anywhere
+    ///
+    /// A port on its own is served on localhost only
+    pub fn metrics_address(&self) -> Result<Option<SocketAddr>> {
+        let Some(address) = &self.metrics_address else {
+            return Ok(None);
+        };
+        let address = match address.parse::<u16>() {
+            Ok(port) => SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            Err(_) => address.parse().map_err(|_| {
+                anyhow::anyhow!(
+                    "Invalid metrics address {}, expected a port or an address and port",
+                    address
+                )
+            })?,
+        };
+        Ok(Some(address))
+    }
+
+    /// The retry policy for a share, inheriting unset values from its server and the defaults
+    pub fn retry_policy(&self, server: &ServerConfig, share: &ShareConfig) -> RetryPolicy {
+        share.retry.or(&server.retry).or(&self.retry).to_policy()
+    }
+
+    /// The health check for a share, inheriting unset values from its server and the defaults
+    pub fn health_check(&self, server: &ServerConfig, share: &ShareConfig) -> HealthCheck {
+        share
+            .health
+            .or(&server.health)
+            .or(&self.health)
+            .to_health_check()
+    }
+}
+
+impl HealthConfig {
+    /// Fill in any unset values from another health configuration
+    fn or(&self, other: &HealthConfig) -> HealthConfig {
+        HealthConfig {
+            marker: self.marker.clone().or_else(|| other.marker.clone()),
+            marker_content: self
+                .marker_content
+                .clone()
+                .or_else(|| other.marker_content.clone()),
+            timeout: self.timeout.or(other.timeout),
+            list_directory: self.list_directory.or(other.list_directory),
+            write_probe: self.write_probe.or(other.write_probe),
+            recover_stale: self.recover_stale.or(other.recover_stale),
+        }
+    }
+
+    /// Build a health check, using the defaults for any unset values
+    fn to_health_check(&self) -> HealthCheck {
+        let default = HealthCheck::default();
+        HealthCheck {
+            marker: self.marker.as_ref().map_or(default.marker, PathBuf::from),
+            marker_content: self.marker_content.clone(),
+            timeout: self.timeout.unwrap_or(default.timeout),
+            list_directory: self.list_directory.unwrap_or(default.list_directory),
+            write_probe: self.write_probe.unwrap_or(default.write_probe),
+            recover_stale: self.recover_stale.unwrap_or(default.recover_stale),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Fill in any unset values from another retry configuration
+    fn or(&self, other: &RetryConfig) -> RetryConfig {
+        RetryConfig {
+            initial_delay: self.initial_delay.or(other.initial_delay),
+            multiplier: self.multiplier.or(other.multiplier),
+            max_delay: self.max_delay.or(other.max_delay),
+            max_attempts: self.max_attempts.or(other.max_attempts),
+            jitter: self.jitter.or(other.jitter),
+        }
+    }
+
+    /// Build a retry policy, using the defaults for any unset values
+    fn to_policy(&self) -> RetryPolicy {
+        let default = RetryPolicy::default();
+        RetryPolicy {
+            initial_delay: self.initial_delay.unwrap_or(default.initial_delay),
+            multiplier: self.multiplier.unwrap_or(default.multiplier),
+            max_delay: self.max_delay.unwrap_or(default.max_delay),
+            max_attempts: self.max_attempts.or(default.max_attempts),
+            jitter: self.jitter.unwrap_or(default.jitter),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Check whether this server would be set up differently under another configuration
+    pub fn differs(&self, global: &Config, other: &ServerConfig, other_global: &Config) -> bool {
+        self != other
+            || self.backend_kind(global) != other.backend_kind(other_global)
+            || self.probe_kind(global) != other.probe_kind(other_global)
+            || self.port(global) != other.port(other_global)
+            || self.probe_timeout(global) != other.probe_timeout(other_global)
+            || self.poll_interval(global) != other.poll_interval(other_global)
+            || global.mount_root != other_global.mount_root
+            || global.retry != other_global.retry
+            || global.health != other_global.health
+    }
+
+    /// The backend used for this server, falling back to the global default
+    pub fn backend_kind(&self, config: &Config) -> BackendKind {
+        self.backend.or(config.backend).unwrap_or_default()
+    }
+
+    /// Every name the server is known by, in priority order
+    pub fn candidate_hosts(&self) -> Vec<String> {
+        std::iter::once(&self.host)
+            .chain(&self.fallback_hosts)
+            .cloned()
+            .collect()
+    }
+
+    /// The SMB port of this server, falling back to the global default
+    pub fn port(&self, config: &Config) -> u16 {
+        self.port.or(config.port).unwrap_or(DEFAULT_PORT)
+    }
+
+    /// How long a reachability probe of this server may take, falling back to the global default
+    pub fn probe_timeout(&self, config: &Config) -> Duration {
+        self.probe_timeout
+            .or(config.probe_timeout)
+            .unwrap_or(DEFAULT_PROBE_TIMEOUT)
+    }
+
+    /// How often this server is probed, falling back to the global default
+    pub fn poll_interval(&self, config: &Config) -> Duration {
+        self.poll_interval
+            .or(config.poll_interval)
+            .unwrap_or(DEFAULT_POLL_INTERVAL)
+    }
+
+    /// The reachability probe used for this server, falling back to the global default
+    pub fn probe_kind(&self, config: &Config) -> ProbeKind {
+        self.probe.or(config.probe).unwrap_or_default()
+    }
+}
+
+impl ShareConfig {
+    /// Parse a share specification of the form `name` or `name=mount_point`
+    pub fn parse(spec: &str) -> Result<Self> {
+        // Split off the optional mount point
+        let (name, mount_point) = match spec.split_once('=') {
+            Some((name, mount_point)) => (name, Some(mount_point.trim())),
+            None => (spec, None),
+        };
+
+        // Strip whitespace and any leading slash from the share name
+        let name = name.trim().trim_start_matches('/');
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Invalid share name in '{}'", spec));
+        }
+        if mount_point == Some("") {
+            return Err(anyhow::anyhow!("Empty mount point in '{}'", spec));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            mount_point: mount_point.map(str::to_string),
+            retry: RetryConfig::default(),
+            health: HealthConfig::default(),
+        })
+    }
+}
+
+/// Parse a comma-separated list of share specifications
+pub fn parse_shares(specs: &str) -> Result<Vec<ShareConfig>> {
+    specs
+        .split(',')
+        .filter(|spec| !spec.trim().is_empty())
+        .map(ShareConfig::parse)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse and validate a configuration
+    fn parse(contents: &str) -> Result<Config> {
+        let config: Config = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn accepts_credentials_for_the_cifs_backend() {
+        let config = parse(
+            r#"
+            [[server]]
+            host = "nas.local"
+            backend = "cifs"
+            cifs = { credentials = "/etc/remounter/nas.cred" }
+            share = [{ name = "Media" }]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.servers[0].cifs.credentials,
+            Some(PathBuf::from("/etc/remounter/nas.cred"))
+        );
+    }
+
+    #[test]
+    fn rejects_credentials_for_other_backends() {
+        let error = parse(
+            r#"
+            backend = "applescript"
+
+            [[server]]
+            host = "nas.local"
+            cifs = { credentials = "/etc/remounter/nas.cred" }
+            share = [{ name = "Media" }]
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Credentials for nas.local are only used by the cifs backend"
+        );
+
+        // The old top-level field is gone
+        assert!(
+            parse(
+                r#"
+                [[server]]
+                host = "nas.local"
+                backend = "cifs"
+                credentials = "/etc/remounter/nas.cred"
+                share = [{ name = "Media" }]
+                "#,
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_durations_too_long_to_schedule() {
+        let config = |settings: &str| {
+            parse(&format!(
+                "{}\n[[server]]\nhost = \"nas.local\"\nshare = [{{ name = \"Media\" }}]\n",
+                settings
+            ))
+        };
+        assert!(config("poll_interval = \"1day\"").is_ok());
+        assert!(config("poll_interval = \"10000y\"").is_err());
+        assert!(config("probe_timeout = \"10000y\"").is_err());
+        assert!(config("shutdown_timeout = \"10000y\"").is_err());
+        assert!(config("[retry]\nmax_delay = \"10000y\"").is_err());
+        assert!(config("[retry]\ninitial_delay = \"10000y\"").is_err());
+        assert!(config("[health]\ntimeout = \"10000y\"").is_err());
+    }
+
+    #[test]
+    fn detects_server_changes() {
+        let base = r#"
+            [[server]]
+            host = "nas.local"
+            share = [{ name = "Media" }]
+
+            [[server]]
+            host = "backup.local"
+            port = 4455
+            share = [{ name = "Backups" }]
+            "#;
+        let old = parse(base).unwrap();
+        let differs = |contents: &str, index: usize| {
+            let new = parse(contents).unwrap();
+            old.servers[index].differs(&old, &new.servers[index], &new)
+        };
+
+        // Nothing changed
+        assert!(!differs(base, 0));
+
+        // A server's own settings only affect that server
+        let changed = base.replace("port = 4455", "port = 4456");
+        assert!(!differs(&changed, 0));
+        assert!(differs(&changed, 1));
+
+        // Inherited settings affect every server that does not override them
+        let changed = format!("port = 4456\n{}", base);
+        assert!(differs(&changed, 0));
+        assert!(!differs(&changed, 1));
+
+        // Global share settings affect every server
+        for global in [
+            "mount_root = \"/srv\"",
+            "[retry]\nmax_attempts = 3",
+            "[health]\nlist_directory = true",
+        ] {
+            let changed = format!("{}\n{}", global, base);
+            assert!(differs(&changed, 0), "{}", global);
+            assert!(differs(&changed, 1), "{}", global);
+        }
+    }
+}
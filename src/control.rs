@@ -0,0 +1,213 @@
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{remounter::Event, status::Status};
+
+/// How long a client may take to send its request, and either side to accept a reply
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the daemon may take to act on a request, long enough for a slow mount
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A request sent to the daemon over the control socket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// Report the state of every server and share
+    Status,
+    /// Check a share straight away, mounting it if needed
+    Remount {
+        /// The share, as `name` or `host/name`
+        share: String,
+    },
+    /// Unmount a share and leave it unmounted until it is remounted
+    Unmount {
+        /// The share, as `name` or `host/name`
+        share: String,
+    },
+    /// Stop probing and remounting, indefinitely or for a while
+    Pause {
+        /// How long to pause for
+        duration: Option<Duration>,
+    },
+    /// Start probing and remounting again after a pause
+    Resume,
+}
+
+/// The daemon's answer to a request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Response {
+    /// The current status
+    Status(Status),
+    /// The request was handled
+    Ok(String),
+    /// The request could not be handled
+    Error(String),
+}
+
+/// The control socket a daemon is listening on, removed again when dropped
+#[derive(Debug)]
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The default control socket path
+///
+/// This is `$XDG_RUNTIME_DIR/remounter.sock` where that is set, and a
+/// per-user socket in `/tmp` otherwise
+pub fn default_socket_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) if !runtime_dir.is_empty() => {
+            PathBuf::from(runtime_dir).join("remounter.sock")
+        }
+        // SAFETY: getuid has no memory safety requirements and cannot fail
+        _ => PathBuf::from(format!("/tmp/remounter-{}.sock", unsafe { libc::getuid() })),
+    }
+}
+
+/// Listen for control requests
+///
+/// Status requests are answered from the shared snapshot, everything else is
+/// passed on to the main loop through `events`
+pub fn listen(
+    path: &Path,
+    status: Arc<Mutex<Status>>,
+    events: Sender<Event>,
+) -> Result<ControlSocket> {
+    // Replace a socket left behind by a daemon that did not exit cleanly, but never a live
+    // one, and never anything that is not a socket
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(anyhow::anyhow!(
+                "{} already exists and is not a socket",
+                path.display()
+            ));
+        }
+        Ok(_) => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(anyhow::anyhow!(
+                    "Another remounter is already listening on {}",
+                    path.display()
+                ));
+            }
+            fs::remove_file(path)
+                .with_context(|| format!("Could not remove stale socket {}", path.display()))?;
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("Could not inspect {}", path.display()));
+        }
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Only the user running the daemon may talk to it
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Could not listen on {}", path.display()))?;
+    let socket = ControlSocket {
+        path: path.to_path_buf(),
+    };
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    info!("Listening for control requests on {}", path.display());
+
+    // Answer each client in turn, the main loop handles one request at a time anyway
+    thread::Builder::new()
+        .name("control".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream
+                    .map_err(anyhow::Error::from)
+                    .and_then(|stream| handle(stream, &status, &events));
+                if let Err(e) = result {
+                    warn!("Error handling control request: {:#}", e);
+                }
+            }
+        })?;
+
+    Ok(socket)
+}
+
+/// Read a single request from a client and write the response
+fn handle(mut stream: UnixStream, status: &Mutex<Status>, events: &Sender<Event>) -> Result<()> {
+    // Read the request, one JSON object on a single line
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    // Work out the response
+    debug!("Control request {:?}", line.trim());
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(Request::Status) => {
+            Response::Status(status.lock().unwrap_or_else(|e| e.into_inner()).clone())
+        }
+        Ok(request) => forward(request, events),
+        Err(e) => Response::Error(format!("Invalid request: {}", e)),
+    };
+
+    // Send it back on a single line
+    let mut response = serde_json::to_string(&response)?;
+    response.push('\n');
+    stream.write_all(response.as_bytes())?;
+
+    Ok(())
+}
+
+/// Pass a request to the main loop and wait for it to be handled
+fn forward(request: Request, events: &Sender<Event>) -> Response {
+    let (sender, receiver) = mpsc::channel();
+    if events.send(Event::Request(request, sender)).is_err() {
+        return Response::Error("The daemon is shutting down".to_string());
+    }
+    receiver.recv_timeout(RESPONSE_TIMEOUT).unwrap_or_else(|_| {
+        Response::Error("The daemon did not handle the request in time".to_string())
+    })
+}
+
+/// Send a request to the running daemon and wait for its response
+pub fn send(path: &Path, request: &Request) -> Result<Response> {
+    // Connect to the daemon
+    let mut stream = UnixStream::connect(path).with_context(|| {
+        format!(
+            "Could not connect to the remounter daemon on {}, is it running?",
+            path.display()
+        )
+    })?;
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT + TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    // Send the request on a single line
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    // Read the response
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    serde_json::from_str(&line).context("Invalid response from the remounter daemon")
+}
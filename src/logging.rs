@@ -0,0 +1,356 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use time::format_description::well_known::Rfc3339;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{
+    EnvFilter, Layer,
+    fmt::{self, time::UtcTime, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+};
+
+/// The level logged when neither `--log-level` nor `RUST_LOG` is given
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::DEBUG;
+
+/// The default number of rotated log files to keep
+pub const DEFAULT_KEEP: usize = 5;
+
+/// How log messages are formatted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Where log messages go
+pub enum Destination {
+    /// Standard output, used by the daemon
+    Stdout,
+    /// Standard error, used by commands that print their results on standard output
+    Stderr,
+    /// A log file
+    File(LogFile),
+}
+
+/// Set up logging
+///
+/// `level` takes the same directives as `RUST_LOG`, and wins over it
+pub fn init(format: LogFormat, level: Option<&str>, destination: Destination) -> Result<()> {
+    // Work out which messages to log
+    let filter = match level {
+        Some(level) => {
+            EnvFilter::try_new(level).with_context(|| format!("Invalid log level {}", level))?
+        }
+        None => EnvFilter::builder()
+            .with_default_directive(DEFAULT_LEVEL.into())
+            .from_env()
+            .context("Invalid RUST_LOG")?,
+    };
+
+    // Only colour the console
+    let ansi = !matches!(destination, Destination::File(_));
+    let writer = match destination {
+        Destination::Stdout => BoxMakeWriter::new(io::stdout),
+        Destination::Stderr => BoxMakeWriter::new(io::stderr),
+        Destination::File(file) => BoxMakeWriter::new(Mutex::new(file)),
+    };
+
+    // Format each message with an RFC3339 UTC timestamp
+    let layer = match format {
+        LogFormat::Text => fmt::layer()
+            .with_timer(UtcTime::new(Rfc3339))
+            .with_target(true)
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_timer(UtcTime::new(Rfc3339))
+            .with_target(true)
+            .with_writer(writer)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(filter)
+        .init();
+
+    Ok(())
+}
+
+/// How often the log file is rotated regardless of its size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Rotation {
+    /// At the start of every hour (UTC)
+    Hourly,
+    /// At midnight (UTC)
+    Daily,
+}
+
+impl Rotation {
+    /// The length of a rotation period in seconds
+    fn seconds(&self) -> u64 {
+        match self {
+            Rotation::Hourly => 60 * 60,
+            Rotation::Daily => 24 * 60 * 60,
+        }
+    }
+}
+
+/// A log file that is rotated once it grows too large or a new period starts
+///
+/// Rotated files are renamed to `<path>.1`, `<path>.2` and so on, newest
+/// first, and only the configured number of them are kept
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+    rotation: Option<Rotation>,
+    period: u64,
+    keep: usize,
+}
+
+impl LogFile {
+    /// Open a log file for appending, creating it and its directory if needed
+    pub fn open(
+        path: &Path,
+        max_size: Option<u64>,
+        rotation: Option<Rotation>,
+        keep: usize,
+    ) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file =
+            open(path).with_context(|| format!("Could not open log file {}", path.display()))?;
+        let size = file.metadata()?.len();
+
+        // A file left over from an earlier period is rotated on the first write
+        let modified = file.metadata()?.modified().ok();
+        let period = rotation.map_or(0, |rotation| period(rotation, modified));
+
+        Ok(LogFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            rotation,
+            period,
+            keep,
+        })
+    }
+
+    /// Check whether the file should be rotated before writing `len` more bytes
+    fn needs_rotation(&self, len: usize) -> bool {
+        let too_large = self
+            .max_size
+            .is_some_and(|max_size| self.size > 0 && self.size + len as u64 > max_size);
+        let new_period = self
+            .rotation
+            .is_some_and(|rotation| period(rotation, None) != self.period);
+        too_large || new_period
+    }
+
+    /// Move the current file aside, dropping the oldest, and start a new one
+    fn rotate(&mut self) -> io::Result<()> {
+        // Shift the rotated files along, the last one falls off the end
+        let rotated = |index: usize| PathBuf::from(format!("{}.{}", self.path.display(), index));
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated(self.keep));
+            for index in (1..self.keep).rev() {
+                let _ = fs::rename(rotated(index), rotated(index + 1));
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+
+        // Carry on in a fresh file
+        self.file = open(&self.path)?;
+        self.size = 0;
+        if let Some(rotation) = self.rotation {
+            self.period = period(rotation, None);
+        }
+        Ok(())
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Rotate between log lines, keep writing to the old file if rotating fails
+        if self.needs_rotation(buf.len())
+            && let Err(e) = self.rotate()
+        {
+            eprintln!("Could not rotate {}: {}", self.path.display(), e);
+            self.size = 0;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Open a file for appending
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// The rotation period a time falls in, defaulting to now
+fn period(rotation: Rotation, time: Option<SystemTime>) -> u64 {
+    time.unwrap_or_else(SystemTime::now)
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / rotation.seconds())
+}
+
+/// Parse a size such as `10MB`, `512K` or `1048576`, using binary multiples
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let digits = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(digits);
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!("invalid size {:?}", size))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(format!("invalid size unit {:?}, expected K, M or G", unit)),
+    };
+    match number.checked_mul(multiplier) {
+        Some(0) => Err("size must be greater than zero".to_string()),
+        Some(bytes) => Ok(bytes),
+        None => Err(format!("size {:?} is too large", size)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for a test's log files
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("remounter-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// The contents of a file, or None if it does not exist
+    fn read(path: impl AsRef<Path>) -> Option<String> {
+        fs::read_to_string(path).ok()
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1048576"), Ok(1 << 20));
+        assert_eq!(parse_size("100B"), Ok(100));
+        assert_eq!(parse_size("512K"), Ok(512 << 10));
+        assert_eq!(parse_size("10MB"), Ok(10 << 20));
+        assert_eq!(parse_size(" 2 gib "), Ok(2 << 30));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size("10TB").is_err());
+        assert_eq!(
+            parse_size("0K"),
+            Err("size must be greater than zero".to_string())
+        );
+        assert!(parse_size("99999999999G").is_err());
+    }
+
+    #[test]
+    fn rotates_when_too_large() {
+        let dir = temp_dir("size");
+        let path = dir.join("remounter.log");
+        let mut file = LogFile::open(&path, Some(10), None, DEFAULT_KEEP).unwrap();
+
+        // An empty file always takes the next line, however long
+        assert!(!file.needs_rotation(20));
+        file.write_all(b"first\n").unwrap();
+        assert!(!file.needs_rotation(4));
+        assert!(file.needs_rotation(5));
+
+        // Writing the line that does not fit starts a new file
+        file.write_all(b"second\n").unwrap();
+        assert_eq!(read(&path).as_deref(), Some("second\n"));
+        assert_eq!(
+            read(dir.join("remounter.log.1")).as_deref(),
+            Some("first\n")
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_when_a_new_period_starts() {
+        let dir = temp_dir("period");
+        let path = dir.join("remounter.log");
+        let mut file = LogFile::open(&path, None, Some(Rotation::Hourly), DEFAULT_KEEP).unwrap();
+        assert!(!file.needs_rotation(1 << 30));
+
+        file.period -= 1;
+        assert!(file.needs_rotation(1));
+        file.rotate().unwrap();
+        assert!(!file.needs_rotation(1));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_newest_rotated_files() {
+        let dir = temp_dir("keep");
+        let path = dir.join("remounter.log");
+        let mut file = LogFile::open(&path, None, None, 2).unwrap();
+        for line in ["one\n", "two\n", "three\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+            file.rotate().unwrap();
+        }
+        file.write_all(b"four\n").unwrap();
+
+        assert_eq!(read(&path).as_deref(), Some("four\n"));
+        assert_eq!(
+            read(dir.join("remounter.log.1")).as_deref(),
+            Some("three\n")
+        );
+        assert_eq!(read(dir.join("remounter.log.2")).as_deref(), Some("two\n"));
+        assert_eq!(read(dir.join("remounter.log.3")), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_no_rotated_files() {
+        let dir = temp_dir("none");
+        let path = dir.join("remounter.log");
+        let mut file = LogFile::open(&path, None, None, 0).unwrap();
+        file.write_all(b"one\n").unwrap();
+        file.rotate().unwrap();
+        file.write_all(b"two\n").unwrap();
+
+        assert_eq!(read(&path).as_deref(), Some("two\n"));
+        assert_eq!(read(dir.join("remounter.log.1")), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,67 +1,408 @@
+mod backend;
+mod config;
+mod control;
+mod logging;
+mod metrics;
+mod mount_table;
+mod net;
+mod network;
+mod probe;
+mod process;
+mod random;
 mod remounter;
+mod retry;
+mod server;
+mod share;
+mod smb;
+mod status;
 
-use std::path::Path;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use clap::Parser;
+use anyhow::Result;
+use clap::{
+    Arg, Args as _, CommandFactory, FromArgMatches, Parser, Subcommand, error::ErrorKind,
+    parser::ValueSource,
+};
 
 use tracing::{error, info, instrument};
-use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::remounter::new_remounter;
+use crate::{
+    backend::{BackendKind, CifsOptions},
+    config::{Config, HealthConfig, RetryConfig, ServerConfig, parse_shares},
+    control::{Request, Response},
+    logging::{Destination, LogFile, LogFormat, Rotation},
+    remounter::new_remounter,
+    server::ProbeKind,
+    status::Status,
+};
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    override_usage = "remounter [OPTIONS] <HOST> <SMB_SHARES>\n       remounter [OPTIONS] --config <CONFIG>\n       remounter [OPTIONS] <COMMAND>",
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    /// Run a one-off command instead of starting the daemon
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The Unix socket the daemon listens on for control requests
+    #[arg(long, global = true)]
+    control_socket: Option<PathBuf>,
+
+    /// How log messages are formatted
+    #[arg(long, value_enum, global = true, default_value_t)]
+    log_format: LogFormat,
+
+    /// Which messages to log, as a level (e.g., info) or directives like RUST_LOG [default: RUST_LOG, or debug]
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+
+    /// Write log messages to this file instead of the console
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Rotate the log file once it would grow beyond this size (e.g., 10MB)
+    #[arg(long, global = true, requires = "log_file", value_parser = logging::parse_size)]
+    log_max_size: Option<u64>,
+
+    /// Also rotate the log file every hour or every day
+    #[arg(long, value_enum, global = true, requires = "log_file")]
+    log_rotate: Option<Rotation>,
+
+    /// How many rotated log files to keep
+    #[arg(long, global = true, requires = "log_file", default_value_t = logging::DEFAULT_KEEP)]
+    log_keep: usize,
+
+    /// The servers and shares to monitor
+    #[command(flatten)]
+    config_args: ConfigArgs,
+}
+
+// The servers and shares to monitor, from a configuration file or the command line
+// (not a doc comment, which clap would use as the description of every command)
+#[derive(clap::Args)]
+struct ConfigArgs {
     /// The hostname to monitor (e.g., example.com)
-    host: String,
+    #[arg(required_unless_present = "config")]
+    host: Option<String>,
+
+    /// The SMB shares to remount (comma-separated names, optionally as name=mount_point)
+    #[arg(required_unless_present = "config")]
+    smb_shares: Option<String>,
+
+    /// A TOML configuration file describing the servers and shares to monitor
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = ["host", "smb_shares", "fallback_hosts", "mount_root", "post_mount_script", "backend", "port", "probe", "probe_timeout", "poll_interval", "shutdown_timeout", "unmount_on_exit", "metrics_address", "recover_stale", "vers", "uid", "gid", "file_mode", "credentials"]
+    )]
+    config: Option<PathBuf>,
+
+    /// Other names or addresses of the server, tried in order when the host is unreachable (comma-separated)
+    #[arg(short, long, value_delimiter = ',')]
+    fallback_hosts: Vec<String>,
 
-    /// The SMB shares to remount (comma-separated paths)
-    smb_shares: String,
+    /// The directory shares are mounted under (defaults to the backend's mount root)
+    #[arg(short, long)]
+    mount_root: Option<String>,
 
     /// A script to run after remounting
     #[arg(short, long)]
     post_mount_script: Option<String>,
+
+    /// The backend used to mount shares
+    #[arg(short, long, value_enum)]
+    backend: Option<BackendKind>,
+
+    /// The SMB port on the server [default: 445]
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// How the server's reachability is checked
+    #[arg(long, value_enum)]
+    probe: Option<ProbeKind>,
+
+    /// How long a reachability probe may take (e.g., 10s) [default: 10s]
+    #[arg(long, value_parser = humantime::parse_duration)]
+    probe_timeout: Option<Duration>,
+
+    /// How often the server is probed (e.g., 30s) [default: 1s]
+    #[arg(long, value_parser = humantime::parse_duration)]
+    poll_interval: Option<Duration>,
+
+    /// How long shutting down may take before running commands are killed (e.g., 10s) [default: 10s]
+    #[arg(long, value_parser = humantime::parse_duration)]
+    shutdown_timeout: Option<Duration>,
+
+    /// Unmount the shares the daemon mounted itself when it exits
+    #[arg(long)]
+    unmount_on_exit: bool,
+
+    /// Serve Prometheus metrics on this port on localhost, or on this address and port
+    #[arg(long)]
+    metrics_address: Option<String>,
+
+    /// Force-unmount and remount shares that are mounted but not healthy
+    #[arg(long)]
+    recover_stale: bool,
+
+    /// Options used by the cifs backend
+    #[command(flatten)]
+    cifs_options: CifsOptions,
 }
 
-#[instrument]
-fn main() {
-    // Create a human-readable time formatter
-    let custom_format = time::format_description::well_known::Rfc3339;
+/// Commands run instead of the daemon
+#[derive(Subcommand)]
+enum Command {
+    /// Show each server's reachability and the state of each share as seen by the running daemon
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check a share straight away, mounting it if needed, even if it gave up or was unmounted
+    Remount {
+        /// The share, as name or host/name
+        share: String,
+    },
+    /// Unmount a share and leave it unmounted until it is remounted
+    Unmount {
+        /// The share, as name or host/name
+        share: String,
+    },
+    /// Stop probing servers and remounting shares
+    Pause {
+        /// Resume by itself after this long (e.g., 30m) instead of waiting for resume
+        #[arg(long = "for", value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// Start probing servers and remounting shares again after a pause
+    Resume,
+    /// Probe each server once and report the state of each share without mounting anything
+    Check {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
 
-    // Human-readable console logs (with colours)
-    let console_layer = fmt::layer()
-        .with_timer(fmt::time::UtcTime::new(custom_format))
-        .with_target(true);
+        /// The servers and shares to check
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
+    /// Probe each server once, mount any shares that need it and exit
+    MountNow {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
 
-    // Initialize the tracing subscriber with both layers
-    tracing_subscriber::registry().with(console_layer).init();
+        /// The servers and shares to mount
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
+}
+
+impl Command {
+    /// Run the command and print the result, returning the exit code
+    fn run(&self, socket: &Path) -> Result<i32> {
+        // Build the request for the daemon, or run a check without one
+        let request = match self {
+            Command::Status { .. } => Request::Status,
+            Command::Remount { share } => Request::Remount {
+                share: share.clone(),
+            },
+            Command::Unmount { share } => Request::Unmount {
+                share: share.clone(),
+            },
+            Command::Pause { duration } => Request::Pause {
+                duration: *duration,
+            },
+            Command::Resume => Request::Resume,
+            Command::Check { json, config_args } => {
+                let mut remounter = new_remounter(config_args.config()?, None, None)?;
+                print_status(&remounter.check_once()?, *json)?;
+                return Ok(remounter.exit_code());
+            }
+            Command::MountNow { json, config_args } => {
+                let mut remounter = new_remounter(config_args.config()?, None, None)?;
+                print_status(&remounter.mount_once()?, *json)?;
+                return Ok(remounter.exit_code());
+            }
+        };
+
+        // Print whatever the daemon answered
+        match control::send(socket, &request)? {
+            Response::Status(status) => {
+                print_status(&status, matches!(self, Command::Status { json: true }))?
+            }
+            Response::Ok(message) => println!("{}", message),
+            Response::Error(e) => return Err(anyhow::anyhow!(e)),
+        }
+        Ok(0)
+    }
+}
 
+impl Args {
+    /// Parse the command line, rejecting daemon options given together with a command
+    ///
+    /// Options such as `--control-socket` may come before or after a command,
+    /// but the servers and shares to monitor only apply to the daemon
+    fn parse_checked() -> Self {
+        let mut command = Args::command();
+        let matches = command.get_matches_mut();
+        if matches.subcommand().is_some() {
+            let daemon_options = ConfigArgs::augment_args(clap::Command::new("daemon"));
+            let given = |option: &Arg| {
+                daemon_options
+                    .get_arguments()
+                    .any(|daemon_option| daemon_option.get_id() == option.get_id())
+                    && matches.value_source(option.get_id().as_str())
+                        == Some(ValueSource::CommandLine)
+            };
+            let conflict = command
+                .get_arguments()
+                .find(|option| given(option))
+                .map(|option| match option.get_long() {
+                    Some(long) => format!("--{}", long),
+                    None => format!("<{}>", option.get_id().as_str().to_uppercase()),
+                });
+            if let Some(option) = conflict {
+                command
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!(
+                            "{} cannot be given before a command, pass it after `check` or `mount-now` instead",
+                            option
+                        ),
+                    )
+                    .exit();
+            }
+        }
+        Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+    }
+
+    /// Set up logging to the console or the log file
+    fn init_logging(&self) -> Result<()> {
+        // Commands print their results on stdout, so their logs go to stderr
+        let destination = match (&self.log_file, &self.command) {
+            (Some(path), _) => Destination::File(LogFile::open(
+                path,
+                self.log_max_size,
+                self.log_rotate,
+                self.log_keep,
+            )?),
+            (None, Some(_)) => Destination::Stderr,
+            (None, None) => Destination::Stdout,
+        };
+        logging::init(self.log_format, self.log_level.as_deref(), destination)
+    }
+}
+
+/// Print a status report, as text or as JSON
+fn print_status(status: &Status, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(status)?);
+    } else {
+        print!("{}", status);
+    }
+    Ok(())
+}
+
+impl ConfigArgs {
+    /// Load the configuration file, or build a single-server configuration from the arguments
+    fn config(&self) -> Result<Config> {
+        // Prefer the configuration file if one was given
+        if let Some(path) = &self.config {
+            return Config::load(path);
+        }
+
+        // Otherwise describe the single server given on the command line
+        let server = ServerConfig {
+            host: self.host.clone().unwrap_or_default(),
+            fallback_hosts: self.fallback_hosts.clone(),
+            port: self.port,
+            probe_timeout: self.probe_timeout,
+            poll_interval: self.poll_interval,
+            backend: self.backend,
+            mount_root: self.mount_root.clone(),
+            post_mount_script: self.post_mount_script.clone(),
+            cifs: self.cifs_options.clone(),
+            probe: self.probe,
+            retry: RetryConfig::default(),
+            health: HealthConfig::default(),
+            shares: parse_shares(self.smb_shares.as_deref().unwrap_or_default())?,
+        };
+        let config = Config {
+            health: HealthConfig {
+                recover_stale: Some(self.recover_stale),
+                ..HealthConfig::default()
+            },
+            shutdown_timeout: self.shutdown_timeout,
+            unmount_on_exit: Some(self.unmount_on_exit),
+            metrics_address: self.metrics_address.clone(),
+            servers: vec![server],
+            ..Config::default()
+        };
+
+        // Apply the same checks as a configuration file
+        config.validate()?;
+
+        Ok(config)
+    }
+}
+
+#[instrument]
+fn main() {
     // Parse command-line arguments
-    let args = Args::parse();
-    let smb_shares: Vec<&Path> = args
-        .smb_shares
-        .split(',')
-        .map(|share| share.trim().trim_start_matches('/'))
-        .filter(|share| !share.is_empty())
-        .map(Path::new)
-        .collect();
+    let args = Args::parse_checked();
 
-    // Combine the startup message into a single multiline log entry
-    let mut startup_message = format!("Starting remounter version {}\n", env!("CARGO_PKG_VERSION"));
-    startup_message.push_str(&format!("Monitoring SMB shares on {}:\n", args.host));
-    for share in &smb_shares {
-        startup_message.push_str(&format!(" - {}\n", share.display()));
+    // Set up logging, exiting if the options are invalid
+    if let Err(e) = args.init_logging() {
+        eprintln!("Error setting up logging: {:#}", e);
+        std::process::exit(1);
     }
-    if let Some(script) = &args.post_mount_script {
-        startup_message.push_str(&format!("Post-mount script: {}\n", script));
+
+    // Commands are handled without starting the daemon
+    if let Some(command) = &args.command {
+        let socket = args
+            .control_socket
+            .clone()
+            .unwrap_or_else(control::default_socket_path);
+        match command.run(&socket) {
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                error!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
     }
-    info!("{}", startup_message.trim_end());
 
-    // Create the remounter
-    let remounter = new_remounter(args.host, smb_shares, args.post_mount_script);
+    // Load the configuration, exiting if it is invalid
+    let config = match args.config_args.config() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Error loading configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    info!("Starting remounter version {}", env!("CARGO_PKG_VERSION"));
+
+    // Create the remounter, the control socket given on the command line wins over the configuration file
+    let remounter = new_remounter(
+        config,
+        args.config_args.config.clone(),
+        args.control_socket.clone(),
+    );
 
     // Handle any errors that occur during remounter creation or execution
-    let remounter = match remounter {
+    let mut remounter = match remounter {
         Ok(r) => r,
         Err(e) => {
             error!("Error creating remounter: {}", e);
@@ -69,6 +410,23 @@ fn main() {
         }
     };
 
+    // Combine the startup message into a single multiline log entry
+    let mut startup_message = String::new();
+    for server in remounter.servers() {
+        startup_message.push_str(&format!("Monitoring SMB shares on {}:\n", server.host()));
+        for share in server.shares() {
+            startup_message.push_str(&format!(
+                " - {} at {}\n",
+                share.name,
+                share.mount_point.display()
+            ));
+        }
+        if let Some(script) = server.post_mount_script() {
+            startup_message.push_str(&format!("Post-mount script: {}\n", script));
+        }
+    }
+    info!("{}", startup_message.trim_end());
+
     // Run the remounter
     if let Err(e) = remounter.run() {
         error!("Error running remounter: {}", e);
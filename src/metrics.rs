@@ -0,0 +1,326 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use tracing::{debug, info};
+
+use crate::{server::Server, share::ShareState};
+
+/// How long a client may take to send its request and read the response
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The upper bounds of the probe latency histogram buckets, in seconds
+const PROBE_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Everything exported, keyed by host and by host and share
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    servers: BTreeMap::new(),
+    shares: BTreeMap::new(),
+});
+
+/// The current value of every metric
+#[derive(Debug)]
+struct Metrics {
+    servers: BTreeMap<String, ServerMetrics>,
+    shares: BTreeMap<(String, String), ShareMetrics>,
+}
+
+/// The metrics for a server
+#[derive(Debug, Default)]
+struct ServerMetrics {
+    up: bool,
+    probe_buckets: [u64; PROBE_BUCKETS.len()],
+    probe_count: u64,
+    probe_sum: f64,
+    post_mount_runs: u64,
+    post_mount_exit_code: Option<i32>,
+}
+
+/// The metrics for a share
+#[derive(Debug)]
+struct ShareMetrics {
+    state: ShareState,
+    mount_attempts: u64,
+    mount_failures: u64,
+}
+
+/// Record how long probing a server took
+pub fn observe_probe(host: &str, duration: Duration) {
+    let mut metrics = metrics();
+    let server = metrics.servers.entry(host.to_string()).or_default();
+    let seconds = duration.as_secs_f64();
+    for (bucket, &bound) in server.probe_buckets.iter_mut().zip(&PROBE_BUCKETS) {
+        if seconds <= bound {
+            *bucket += 1;
+        }
+    }
+    server.probe_count += 1;
+    server.probe_sum += seconds;
+}
+
+/// Record the exit code of a post-mount script, or -1 if it was killed by a signal
+pub fn record_post_mount_script(host: &str, exit_code: Option<i32>) {
+    let mut metrics = metrics();
+    let server = metrics.servers.entry(host.to_string()).or_default();
+    server.post_mount_runs += 1;
+    server.post_mount_exit_code = Some(exit_code.unwrap_or(-1));
+}
+
+/// Update the gauges and counters that mirror the state of every server and share
+///
+/// Servers and shares that are no longer monitored are dropped
+pub fn update(servers: &[Server]) {
+    let mut metrics = metrics();
+    metrics
+        .servers
+        .retain(|host, _| servers.iter().any(|server| server.host() == host));
+    metrics.shares.clear();
+    for server in servers {
+        metrics
+            .servers
+            .entry(server.host().to_string())
+            .or_default()
+            .up = server.is_reachable();
+        for share in server.shares() {
+            metrics.shares.insert(
+                (server.host().to_string(), share.name.clone()),
+                ShareMetrics {
+                    state: share.state,
+                    mount_attempts: share.mount_attempts(),
+                    mount_failures: share.mount_failures(),
+                },
+            );
+        }
+    }
+}
+
+/// Serve the metrics over HTTP for Prometheus to scrape
+pub fn listen(address: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .with_context(|| format!("Could not listen for metrics requests on {}", address))?;
+    info!("Serving metrics on http://{}/metrics", address);
+
+    // Answer each scrape in turn, they are small and infrequent
+    thread::Builder::new()
+        .name("metrics".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.map_err(anyhow::Error::from).and_then(handle);
+                if let Err(e) = result {
+                    debug!("Error handling metrics request: {:#}", e);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Read a single HTTP request and write the response
+fn handle(mut stream: TcpStream) -> Result<()> {
+    // Read the request line, then skip the headers
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    // Only the metrics page exists
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let (status, content_type, body) = match (method, path) {
+        ("GET" | "HEAD", "/metrics") => ("200 OK", "text/plain; version=0.0.4", render()),
+        ("GET" | "HEAD", _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed\n".to_string(),
+        ),
+    };
+
+    // Send the response and close the connection
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    if method != "HEAD" {
+        response.push_str(&body);
+    }
+    stream.write_all(response.as_bytes())?;
+
+    Ok(())
+}
+
+/// Render every metric in the Prometheus text format
+fn render() -> String {
+    let metrics = metrics();
+    let mut out = String::new();
+
+    // Whether each server is reachable
+    header(
+        &mut out,
+        "remounter_server_up",
+        "gauge",
+        "Whether the server answered the last reachability probe",
+    );
+    for (host, server) in &metrics.servers {
+        let _ = writeln!(
+            out,
+            "remounter_server_up{{host=\"{}\"}} {}",
+            escape(host),
+            server.up as u8
+        );
+    }
+
+    // How long probes take
+    header(
+        &mut out,
+        "remounter_probe_duration_seconds",
+        "histogram",
+        "How long reachability probes take",
+    );
+    for (host, server) in &metrics.servers {
+        let host = escape(host);
+        for (count, bound) in server.probe_buckets.iter().zip(PROBE_BUCKETS) {
+            let _ = writeln!(
+                out,
+                "remounter_probe_duration_seconds_bucket{{host=\"{}\",le=\"{}\"}} {}",
+                host, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "remounter_probe_duration_seconds_bucket{{host=\"{}\",le=\"+Inf\"}} {}",
+            host, server.probe_count
+        );
+        let _ = writeln!(
+            out,
+            "remounter_probe_duration_seconds_sum{{host=\"{}\"}} {}",
+            host, server.probe_sum
+        );
+        let _ = writeln!(
+            out,
+            "remounter_probe_duration_seconds_count{{host=\"{}\"}} {}",
+            host, server.probe_count
+        );
+    }
+
+    // The state of each share, one series per possible state
+    header(
+        &mut out,
+        "remounter_share_state",
+        "gauge",
+        "The state of the share, 1 for the current state and 0 for the others",
+    );
+    for ((host, name), share) in &metrics.shares {
+        for state in ShareState::LABELS {
+            let _ = writeln!(
+                out,
+                "remounter_share_state{{host=\"{}\",share=\"{}\",state=\"{}\"}} {}",
+                escape(host),
+                escape(name),
+                state,
+                (share.state.label() == state) as u8
+            );
+        }
+    }
+
+    // Mount attempts and failures for each share
+    header(
+        &mut out,
+        "remounter_mount_attempts_total",
+        "counter",
+        "Mount attempts for the share",
+    );
+    for ((host, name), share) in &metrics.shares {
+        let _ = writeln!(
+            out,
+            "remounter_mount_attempts_total{{host=\"{}\",share=\"{}\"}} {}",
+            escape(host),
+            escape(name),
+            share.mount_attempts
+        );
+    }
+    header(
+        &mut out,
+        "remounter_mount_failures_total",
+        "counter",
+        "Failed mount attempts for the share",
+    );
+    for ((host, name), share) in &metrics.shares {
+        let _ = writeln!(
+            out,
+            "remounter_mount_failures_total{{host=\"{}\",share=\"{}\"}} {}",
+            escape(host),
+            escape(name),
+            share.mount_failures
+        );
+    }
+
+    // How the post-mount script went
+    header(
+        &mut out,
+        "remounter_post_mount_script_runs_total",
+        "counter",
+        "Runs of the post-mount script",
+    );
+    for (host, server) in &metrics.servers {
+        let _ = writeln!(
+            out,
+            "remounter_post_mount_script_runs_total{{host=\"{}\"}} {}",
+            escape(host),
+            server.post_mount_runs
+        );
+    }
+    header(
+        &mut out,
+        "remounter_post_mount_script_exit_code",
+        "gauge",
+        "The exit code of the last post-mount script run, -1 if it was killed by a signal",
+    );
+    for (host, server) in &metrics.servers {
+        if let Some(exit_code) = server.post_mount_exit_code {
+            let _ = writeln!(
+                out,
+                "remounter_post_mount_script_exit_code{{host=\"{}\"}} {}",
+                escape(host),
+                exit_code
+            );
+        }
+    }
+
+    out
+}
+
+/// Write the help and type lines that introduce a metric
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The metrics, recovering from a poisoned lock
+fn metrics() -> MutexGuard<'static, Metrics> {
+    METRICS.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,403 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+/// Filesystem types used for SMB network mounts
+const NETWORK_FS_TYPES: &[&str] = &["cifs", "smb3", "smbfs"];
+
+/// An entry in the system mount table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEntry {
+    /// What is mounted (e.g., //server/share)
+    pub source: String,
+    /// Where it is mounted
+    pub mount_point: PathBuf,
+    /// The filesystem type (e.g., cifs or smbfs)
+    pub fs_type: String,
+}
+
+/// Why the filesystem mounted at a share's mount point is not the expected share
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// Something other than an SMB share is mounted there
+    NotNetwork { fs_type: String },
+    /// A different SMB share is mounted there
+    WrongSource { expected: String, found: String },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::NotNetwork { fs_type } => {
+                write!(f, "{} is mounted there, not an SMB share", fs_type)
+            }
+            Mismatch::WrongSource { expected, found } => {
+                write!(f, "{} is mounted there, expected {}", found, expected)
+            }
+        }
+    }
+}
+
+impl MountEntry {
+    /// Check whether this entry is an SMB network mount
+    pub fn is_network(&self) -> bool {
+        NETWORK_FS_TYPES.contains(&self.fs_type.as_str())
+    }
+
+    /// Check that this entry is an SMB mount of `//host/share` from any of the given hosts
+    pub fn matches(&self, hosts: &[String], share: &str) -> Result<(), Mismatch> {
+        // Only SMB filesystems can be the expected share
+        if !self.is_network() {
+            return Err(Mismatch::NotNetwork {
+                fs_type: self.fs_type.clone(),
+            });
+        }
+
+        // Compare the server and share names from the mount source
+        let matches = parse_source(&self.source).is_some_and(|(found_host, found_share)| {
+            hosts
+                .iter()
+                .any(|host| hosts_match(&found_host, &host.to_lowercase()))
+                && found_share == share.trim_matches('/').to_lowercase()
+        });
+        if !matches {
+            return Err(Mismatch::WrongSource {
+                expected: hosts
+                    .iter()
+                    .map(|host| format!("//{}/{}", host, share))
+                    .collect::<Vec<_>>()
+                    .join(" or "),
+                found: self.source.clone(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Find the filesystem mounted at the given path, if any
+///
+/// Only the mount table is read, so this does not block on a hung mount
+pub fn find(mount_point: &Path) -> Result<Option<MountEntry>> {
+    // The last entry for a path is the one on top, and therefore visible
+    Ok(mounts()?
+        .into_iter()
+        .rev()
+        .find(|entry| entry.mount_point == mount_point))
+}
+
+/// Split an SMB mount source such as `//user@server:port/share` into its lowercase server and share
+fn parse_source(source: &str) -> Option<(String, String)> {
+    let (authority, share) = source.strip_prefix("//")?.split_once('/')?;
+
+    // Drop any user name and domain (macOS writes //user@server/share)
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+
+    // Drop any port, taking care not to split a bracketed IPv6 address
+    let host = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed
+            .split_once(']')
+            .map_or(bracketed, |(host, _)| host),
+        None => match host.rsplit_once(':') {
+            Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+            _ => host,
+        },
+    };
+
+    Some((
+        percent_decode(host).to_lowercase(),
+        percent_decode(share.trim_end_matches('/')).to_lowercase(),
+    ))
+}
+
+/// Check whether two lowercase server names refer to the same server
+///
+/// Finder may record a Bonjour name such as `nas._smb._tcp.local` for a share
+/// mounted from `nas.local`, otherwise the names must be the same
+fn hosts_match(found: &str, expected: &str) -> bool {
+    found == expected
+        || bonjour_host(found).is_some_and(|host| host == expected)
+        || bonjour_host(expected).is_some_and(|host| host == found)
+}
+
+/// The `.local` host name for a Bonjour SMB service name such as `nas._smb._tcp.local`
+fn bonjour_host(name: &str) -> Option<String> {
+    let label = name.strip_suffix("._smb._tcp.local")?;
+    (!label.is_empty() && !label.contains('.')).then(|| format!("{}.local", label))
+}
+
+/// Decode %XX escapes, as used by macOS in mount sources
+fn percent_decode(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = tail
+            .get(..2)
+            .filter(|_| byte == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Read the mount table from /proc/self/mountinfo
+#[cfg(target_os = "linux")]
+pub fn mounts() -> Result<Vec<MountEntry>> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+    Ok(mountinfo.lines().filter_map(parse_mountinfo_line).collect())
+}
+
+/// Read the mount table with getfsstat, falling back to the output of `mount`
+///
+/// This runs several times per share on every poll, so it avoids starting a process
+#[cfg(target_os = "macos")]
+pub fn mounts() -> Result<Vec<MountEntry>> {
+    statfs_mounts().or_else(|e| {
+        tracing::debug!(
+            "Could not read the mount table, running mount instead: {:#}",
+            e
+        );
+        mount_command()
+    })
+}
+
+/// Read the mount table from the output of `mount`
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn mounts() -> Result<Vec<MountEntry>> {
+    mount_command()
+}
+
+/// Read the mount table from the kernel with getfsstat
+///
+/// Unlike getmntinfo this fills a buffer we own, so it is safe to call from any thread
+#[cfg(target_os = "macos")]
+fn statfs_mounts() -> Result<Vec<MountEntry>> {
+    use std::{ffi::OsString, io, mem, os::unix::ffi::OsStringExt, ptr};
+
+    use anyhow::Context;
+
+    // Count the mounted filesystems, leaving room for a few more to appear before the second call
+    // SAFETY: a null buffer only asks for the number of filesystems
+    let count = unsafe { libc::getfsstat(ptr::null_mut(), 0, libc::MNT_NOWAIT) };
+    if count < 0 {
+        return Err(io::Error::last_os_error()).context("Could not count mounted filesystems");
+    }
+    // SAFETY: statfs is plain old data, so all zeroes is a valid value
+    let mut buffer = vec![unsafe { mem::zeroed::<libc::statfs>() }; count as usize + 8];
+
+    // Fill the buffer without waiting on unresponsive network filesystems
+    let size = (buffer.len() * mem::size_of::<libc::statfs>()) as libc::c_int;
+    // SAFETY: buffer is valid for writes of size bytes
+    let count = unsafe { libc::getfsstat(buffer.as_mut_ptr(), size, libc::MNT_NOWAIT) };
+    if count < 0 {
+        return Err(io::Error::last_os_error()).context("Could not list mounted filesystems");
+    }
+    buffer.truncate(count as usize);
+
+    // The names are NUL terminated within their fixed size arrays
+    let bytes = |name: &[libc::c_char]| {
+        name.iter()
+            .map(|&c| c as u8)
+            .take_while(|&b| b != 0)
+            .collect::<Vec<_>>()
+    };
+    Ok(buffer
+        .iter()
+        .map(|entry| MountEntry {
+            source: String::from_utf8_lossy(&bytes(&entry.f_mntfromname)).into_owned(),
+            mount_point: PathBuf::from(OsString::from_vec(bytes(&entry.f_mntonname))),
+            fs_type: String::from_utf8_lossy(&bytes(&entry.f_fstypename)).into_owned(),
+        })
+        .collect())
+}
+
+/// Read the mount table from the output of `mount`
+#[cfg(not(target_os = "linux"))]
+fn mount_command() -> Result<Vec<MountEntry>> {
+    let output = std::process::Command::new("mount").output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("Failed to read the mount table"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_mount_line)
+        .collect())
+}
+
+/// Parse a line of /proc/self/mountinfo
+///
+/// The format is `id parent major:minor root mount_point options [optional...] - fs_type source super_options`
+#[cfg(target_os = "linux")]
+fn parse_mountinfo_line(line: &str) -> Option<MountEntry> {
+    let (fields, filesystem) = line.split_once(" - ")?;
+    let mount_point = fields.split(' ').nth(4)?;
+    let mut filesystem = filesystem.split(' ');
+    let fs_type = filesystem.next()?;
+    let source = filesystem.next()?;
+
+    Some(MountEntry {
+        source: unescape(source),
+        mount_point: PathBuf::from(unescape(mount_point)),
+        fs_type: fs_type.to_string(),
+    })
+}
+
+/// Undo the octal escaping of spaces, tabs, newlines and backslashes in mountinfo fields
+#[cfg(target_os = "linux")]
+fn unescape(field: &str) -> String {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..3)
+            .filter(|digits| byte == b'\\' && digits.iter().all(|d| (b'0'..=b'7').contains(d)))
+            .and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok());
+        match escaped {
+            Some(escaped) => {
+                bytes.push(escaped);
+                rest = &tail[3..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Parse a line of `mount` output
+///
+/// The format is `source on mount_point (fs_type, options...)`
+#[cfg(not(target_os = "linux"))]
+fn parse_mount_line(line: &str) -> Option<MountEntry> {
+    let (source, rest) = line.split_once(" on ")?;
+    let (mount_point, options) = rest.rsplit_once(" (")?;
+    let fs_type = options.trim_end_matches(')').split(',').next()?.trim();
+
+    Some(MountEntry {
+        source: source.to_string(),
+        mount_point: PathBuf::from(mount_point),
+        fs_type: fs_type.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn parses_mountinfo_lines() {
+        let line = "36 35 0:45 / /mnt/team\\040files rw,relatime shared:1 - cifs //nas/team\\040files rw,vers=3.1.1";
+        assert_eq!(
+            parse_mountinfo_line(line),
+            Some(MountEntry {
+                source: "//nas/team files".to_string(),
+                mount_point: PathBuf::from("/mnt/team files"),
+                fs_type: "cifs".to_string(),
+            })
+        );
+
+        // Any number of optional fields may come before the separator
+        let line = "22 1 8:1 / / rw - ext4 /dev/sda1 rw";
+        assert_eq!(
+            parse_mountinfo_line(line).map(|entry| entry.mount_point),
+            Some(PathBuf::from("/"))
+        );
+        assert_eq!(parse_mountinfo_line("22 1 8:1 / / rw"), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn unescapes_octal_escapes() {
+        assert_eq!(unescape("a\\040b\\011c\\012d\\134e"), "a b\tc\nd\\e");
+        assert_eq!(unescape("plain"), "plain");
+        // Anything that is not three octal digits is left alone
+        assert_eq!(unescape("a\\09b\\04"), "a\\09b\\04");
+    }
+
+    #[test]
+    fn parses_sources() {
+        let host_share = |host: &str, share: &str| Some((host.to_string(), share.to_string()));
+        assert_eq!(parse_source("//NAS/Share"), host_share("nas", "share"));
+        assert_eq!(parse_source("//nas/share/"), host_share("nas", "share"));
+        assert_eq!(
+            parse_source("//DOMAIN;user@nas.local:445/share"),
+            host_share("nas.local", "share")
+        );
+        assert_eq!(
+            parse_source("//user@[fe80::1%25en0]:445/share"),
+            host_share("fe80::1%en0", "share")
+        );
+        assert_eq!(
+            parse_source("//[2001:db8::1]/share"),
+            host_share("2001:db8::1", "share")
+        );
+        assert_eq!(
+            parse_source("//nas/Team%20Files"),
+            host_share("nas", "team files")
+        );
+        assert_eq!(parse_source("/dev/sda1"), None);
+        assert_eq!(parse_source("//nas"), None);
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("Team%20Files"), "Team Files");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%2"), "%zz%2");
+    }
+
+    #[test]
+    fn matches_hosts() {
+        assert!(hosts_match("nas.local", "nas.local"));
+        assert!(hosts_match("nas._smb._tcp.local", "nas.local"));
+        assert!(hosts_match("nas.local", "nas._smb._tcp.local"));
+        assert!(!hosts_match("nas.team-a.example", "nas.team-b.example"));
+        assert!(!hosts_match("nas._smb._tcp.local", "nas.example"));
+        assert!(!hosts_match("10.0.0.1", "10.0.0.2"));
+    }
+
+    #[test]
+    fn checks_entries_against_the_share() {
+        let entry = |source: &str, fs_type: &str| MountEntry {
+            source: source.to_string(),
+            mount_point: PathBuf::from("/mnt/share"),
+            fs_type: fs_type.to_string(),
+        };
+        let hosts = ["nas.local".to_string(), "10.0.0.5".to_string()];
+        assert_eq!(
+            entry("//user@10.0.0.5/Share", "smbfs").matches(&hosts, "share"),
+            Ok(())
+        );
+        assert_eq!(
+            entry("/dev/sda1", "ext4").matches(&hosts, "share"),
+            Err(Mismatch::NotNetwork {
+                fs_type: "ext4".to_string()
+            })
+        );
+        assert_eq!(
+            entry("//nas.local/other", "cifs").matches(&hosts, "share"),
+            Err(Mismatch::WrongSource {
+                expected: "//nas.local/share or //10.0.0.5/share".to_string(),
+                found: "//nas.local/other".to_string(),
+            })
+        );
+    }
+}
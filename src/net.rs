@@ -0,0 +1,106 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use crate::process;
+
+/// How long to wait for one connection attempt before also trying the next address
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Resolve a host name to all of its addresses, IPv6 and IPv4 interleaved
+pub fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    // Look up every address, dropping duplicates but keeping the resolver's order
+    let mut addresses = Vec::new();
+    for address in (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("Could not resolve {}", host))?
+    {
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    if addresses.is_empty() {
+        return Err(anyhow::anyhow!("{} did not resolve to any addresses", host));
+    }
+
+    // Alternate between address families, starting with the family the resolver preferred
+    let (first, second): (Vec<_>, Vec<_>) = addresses
+        .iter()
+        .partition(|address| address.is_ipv6() == addresses[0].is_ipv6());
+    let mut interleaved = Vec::with_capacity(addresses.len());
+    for index in 0..first.len().max(second.len()) {
+        interleaved.extend(first.get(index));
+        interleaved.extend(second.get(index));
+    }
+
+    Ok(interleaved)
+}
+
+/// Connect to the first address that answers, happy-eyeballs style
+///
+/// Attempts are started in order, each one 250ms after the previous, and the
+/// first connection to succeed within the timeout wins
+pub fn connect(addresses: &[SocketAddr], timeout: Duration) -> Result<(TcpStream, SocketAddr)> {
+    if addresses.is_empty() {
+        return Err(anyhow::anyhow!("No addresses to connect to"));
+    }
+
+    // Start a staggered connection attempt for each address
+    let deadline = Instant::now() + timeout;
+    let done = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    for (index, &address) in addresses.iter().enumerate() {
+        let done = Arc::clone(&done);
+        let sender = sender.clone();
+        thread::Builder::new()
+            .name("connect".to_string())
+            .spawn(move || {
+                // Give the earlier attempts a head start
+                thread::sleep(ATTEMPT_DELAY * index as u32);
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if done.load(Ordering::Relaxed) || remaining.is_zero() {
+                    return;
+                }
+                let result = TcpStream::connect_timeout(&address, remaining);
+                let _ = sender.send((address, result));
+            })?;
+    }
+    drop(sender);
+
+    // Take the first successful connection, remembering the last error
+    let mut last_error = io::Error::from(io::ErrorKind::TimedOut);
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match process::recv_timeout(&receiver, remaining) {
+            Ok((address, Ok(stream))) => {
+                done.store(true, Ordering::Relaxed);
+                return Ok((stream, address));
+            }
+            Ok((_, Err(e))) => last_error = e,
+            Err(_) => break,
+        }
+    }
+
+    // Every attempt failed or the deadline passed
+    done.store(true, Ordering::Relaxed);
+    Err(last_error).context("Could not connect to any address")
+}
+
+/// Format a list of addresses for logging
+pub fn format_addresses(addresses: &[SocketAddr]) -> String {
+    addresses
+        .iter()
+        .map(SocketAddr::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,163 @@
+use std::sync::mpsc::Sender;
+
+use tracing::warn;
+
+/// Watch for network changes, sending `event` on the channel for each change
+///
+/// Returns false where no change notifications are available, in which case
+/// the regular poll is the only way changes are noticed
+pub fn watch<T: Clone + Send + 'static>(sender: Sender<T>, event: T) -> bool {
+    match platform::watch(sender, event) {
+        Ok(watching) => watching,
+        Err(e) => {
+            warn!(
+                "Could not watch for network changes, relying on polling: {:#}",
+                e
+            );
+            false
+        }
+    }
+}
+
+/// Check whether any route to the network exists
+///
+/// Loopback routes do not count. Where routes cannot be inspected this
+/// always returns true
+pub fn has_route() -> bool {
+    platform::has_route()
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::{
+        fs, io, mem,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+        sync::mpsc::Sender,
+        thread,
+    };
+
+    use anyhow::{Context, Result};
+    use tracing::warn;
+
+    /// Open a netlink socket subscribed to link, address and route changes
+    pub fn watch<T: Clone + Send + 'static>(sender: Sender<T>, event: T) -> Result<bool> {
+        // SAFETY: socket has no memory safety requirements, the result is checked below
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("Could not open netlink socket");
+        }
+        // SAFETY: fd is a freshly opened socket that nothing else owns
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // Subscribe to the multicast groups for links, addresses and routes
+        // SAFETY: sockaddr_nl is plain old data, so all zeroes is a valid value
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = (libc::RTMGRP_LINK
+            | libc::RTMGRP_IPV4_IFADDR
+            | libc::RTMGRP_IPV6_IFADDR
+            | libc::RTMGRP_IPV4_ROUTE
+            | libc::RTMGRP_IPV6_ROUTE) as u32;
+        // SAFETY: address is a valid sockaddr_nl and the length matches its size
+        let result = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                (&address as *const libc::sockaddr_nl).cast(),
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error()).context("Could not bind netlink socket");
+        }
+
+        // Forward every notification until the receiver goes away
+        thread::Builder::new()
+            .name("network-watch".to_string())
+            .spawn(move || {
+                let mut buffer = [0u8; 8192];
+                loop {
+                    // SAFETY: buffer is valid for writes of its whole length
+                    let received = unsafe {
+                        libc::recv(
+                            socket.as_raw_fd(),
+                            buffer.as_mut_ptr().cast(),
+                            buffer.len(),
+                            0,
+                        )
+                    };
+                    if received < 0 {
+                        let e = io::Error::last_os_error();
+                        match e.kind() {
+                            // Missed notifications still mean something changed
+                            io::ErrorKind::Interrupted => continue,
+                            _ if e.raw_os_error() == Some(libc::ENOBUFS) => {}
+                            _ => {
+                                warn!("Stopped watching for network changes: {}", e);
+                                return;
+                            }
+                        }
+                    }
+                    if sender.send(event.clone()).is_err() {
+                        return;
+                    }
+                }
+            })?;
+
+        Ok(true)
+    }
+
+    /// Look for any usable IPv4 or IPv6 route that is not on the loopback interface
+    pub fn has_route() -> bool {
+        // Interface is the first column of the IPv4 table and flags the fourth
+        let ipv4 = fs::read_to_string("/proc/net/route").map(|table| {
+            table.lines().skip(1).any(|line| {
+                let fields = line.split_whitespace().collect::<Vec<_>>();
+                fields.len() > 3 && is_usable(fields[0], fields[3])
+            })
+        });
+
+        // Interface is the last column of the IPv6 table and flags the ninth
+        let ipv6 = fs::read_to_string("/proc/net/ipv6_route").map(|table| {
+            table.lines().any(|line| {
+                let fields = line.split_whitespace().collect::<Vec<_>>();
+                fields.len() > 9 && is_usable(fields[9], fields[8])
+            })
+        });
+
+        match (ipv4, ipv6) {
+            (Ok(ipv4), Ok(ipv6)) => ipv4 || ipv6,
+            (Ok(found), Err(_)) | (Err(_), Ok(found)) => found,
+            // Without a route table to look at, assume the network is there
+            (Err(_), Err(_)) => true,
+        }
+    }
+
+    /// Check whether a route is up and not on the loopback interface
+    fn is_usable(interface: &str, flags: &str) -> bool {
+        interface != "lo"
+            && u32::from_str_radix(flags, 16).is_ok_and(|flags| flags & libc::RTF_UP as u32 != 0)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use std::sync::mpsc::Sender;
+
+    use anyhow::Result;
+
+    /// Network change notifications are not supported on this platform
+    pub fn watch<T>(_sender: Sender<T>, _event: T) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Routes are not inspected on this platform
+    pub fn has_route() -> bool {
+        true
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::{
+        Arc,
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Result;
+use tracing::{debug, warn};
+
+use crate::{
+    backend::{MountBackend, ShareHealth},
+    process,
+};
+
+/// The default name of the marker file placed in the root of each share
+pub const DEFAULT_MARKER: &str = ".smb_remounter";
+
+/// The longest a health probe may be allowed to take
+const MAX_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Options controlling how the health of a share is probed
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthCheck {
+    /// The marker file that must be readable for the share to be healthy, relative to the share root
+    pub marker: PathBuf,
+    /// The content the marker file must contain, ignoring surrounding whitespace
+    pub marker_content: Option<String>,
+    /// How long a probe may take before the share is considered stale
+    pub timeout: Duration,
+    /// Whether to list the root directory of the share as part of the probe
+    pub list_directory: bool,
+    /// Whether to create, sync, read back and delete a file as part of the probe
+    pub write_probe: bool,
+    /// Whether to force-unmount and remount a share that is stale
+    pub recover_stale: bool,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            marker: PathBuf::from(DEFAULT_MARKER),
+            marker_content: None,
+            timeout: Duration::from_secs(5),
+            list_directory: false,
+            write_probe: false,
+            recover_stale: false,
+        }
+    }
+}
+
+impl HealthCheck {
+    /// Check that the health check options are within range
+    pub fn validate(&self) -> Result<()> {
+        if self.timeout.is_zero() {
+            return Err(anyhow::anyhow!("timeout must be greater than zero"));
+        }
+        if self.timeout > MAX_TIMEOUT {
+            return Err(anyhow::anyhow!(
+                "timeout must not be longer than {}",
+                humantime::format_duration(MAX_TIMEOUT)
+            ));
+        }
+        if self.marker.as_os_str().is_empty()
+            || self.marker.is_absolute()
+            || self
+                .marker
+                .components()
+                .any(|component| component == Component::ParentDir)
+        {
+            return Err(anyhow::anyhow!(
+                "marker must be a relative path inside the share"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Runs health probes on a worker thread so a hung mount cannot block the caller
+///
+/// Filesystem calls on a dead network mount can block forever and cannot be
+/// cancelled, so a probe that times out is left running and no new probe is
+/// started until it finishes
+#[derive(Debug, Default)]
+pub struct Prober {
+    pending: Option<JoinHandle<()>>,
+}
+
+impl Prober {
+    /// Probe the health of the share at the mount point
+    ///
+    /// Returns None if the probe timed out or an earlier probe is still hung
+    pub fn probe(
+        &mut self,
+        backend: &Arc<dyn MountBackend>,
+        mount_point: &Path,
+        check: &HealthCheck,
+    ) -> Option<ShareHealth> {
+        // Don't pile up threads behind a probe that is still hung
+        if let Some(pending) = &self.pending {
+            if !pending.is_finished() {
+                debug!(
+                    "Earlier probe of {} is still running",
+                    mount_point.display()
+                );
+                return None;
+            }
+            self.pending = None;
+        }
+
+        // Run the probe on a worker thread
+        let (sender, receiver) = mpsc::channel();
+        let worker_backend = Arc::clone(backend);
+        let worker_mount_point = PathBuf::from(mount_point);
+        let worker_check = check.clone();
+        let spawned = thread::Builder::new()
+            .name("health-probe".to_string())
+            .spawn(move || {
+                let health = worker_backend.health(&worker_mount_point, &worker_check);
+                let _ = sender.send(health);
+            });
+        let handle = match spawned {
+            Ok(handle) => handle,
+            Err(e) => {
+                warn!("Could not start health probe thread: {}", e);
+                return None;
+            }
+        };
+
+        // Wait for the result, keeping hold of the probe if it times out
+        match process::recv_timeout(&receiver, check.timeout) {
+            Ok(health) => Some(health),
+            Err(RecvTimeoutError::Timeout) => {
+                self.pending = Some(handle);
+                None
+            }
+            Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
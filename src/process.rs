@@ -0,0 +1,169 @@
+use std::{
+    os::unix::process::CommandExt,
+    process::{Command, ExitStatus},
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{Receiver, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use tracing::{error, warn};
+
+/// The default time allowed for a clean shutdown
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often waits check whether the daemon is shutting down
+const TICK: Duration = Duration::from_millis(50);
+
+/// Set once a termination signal has been received
+static TERMINATING: LazyLock<Arc<AtomicBool>> = LazyLock::new(|| Arc::new(AtomicBool::new(false)));
+
+/// Set once the daemon is cleaning up after a termination signal
+static CLEANING_UP: AtomicBool = AtomicBool::new(false);
+
+/// The process groups of running child processes
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// The time allowed for a clean shutdown, in milliseconds
+static SHUTDOWN_TIMEOUT: AtomicU64 = AtomicU64::new(DEFAULT_SHUTDOWN_TIMEOUT.as_millis() as u64);
+
+/// The flag termination signal handlers should set
+pub fn terminating_flag() -> Arc<AtomicBool> {
+    Arc::clone(&TERMINATING)
+}
+
+/// Check whether the daemon is shutting down
+pub fn is_terminating() -> bool {
+    TERMINATING.load(Ordering::Relaxed)
+}
+
+/// Allow commands to run again while cleaning up during shutdown
+///
+/// They are still killed if the shutdown deadline passes
+pub fn start_cleanup() {
+    CLEANING_UP.store(true, Ordering::Relaxed);
+}
+
+/// Check whether running commands should be stopped
+fn should_stop() -> bool {
+    is_terminating() && !CLEANING_UP.load(Ordering::Relaxed)
+}
+
+/// Set the time allowed for a clean shutdown
+pub fn set_shutdown_timeout(timeout: Duration) {
+    SHUTDOWN_TIMEOUT.store(timeout.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Sleep for the given duration, returning early if the daemon starts shutting down
+pub fn sleep(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while !is_terminating() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return;
+        }
+        thread::sleep(remaining.min(TICK));
+    }
+}
+
+/// Wait for a message, giving up early with a timeout if the daemon starts shutting down
+///
+/// A message that is already waiting is always returned, even with a zero timeout
+pub fn recv_timeout<T>(receiver: &Receiver<T>, timeout: Duration) -> Result<T, RecvTimeoutError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(remaining.min(TICK)) {
+            Err(RecvTimeoutError::Timeout) if !remaining.is_zero() && !is_terminating() => {}
+            result => return result,
+        }
+    }
+}
+
+/// Run a command to completion, returning its exit status
+///
+/// The command runs in its own process group, which is sent SIGTERM if the
+/// daemon starts shutting down, and SIGKILL if it is still running when the
+/// shutdown deadline passes. No new commands are started during shutdown,
+/// other than while cleaning up
+pub fn status(command: &mut Command) -> Result<ExitStatus> {
+    if should_stop() {
+        return Err(anyhow::anyhow!(
+            "Not running {:?}, shutting down",
+            command.get_program()
+        ));
+    }
+
+    // Start the command in its own process group so scripts and their children can be signalled together
+    let mut child = command
+        .process_group(0)
+        .spawn()
+        .with_context(|| format!("Could not run {:?}", command.get_program()))?;
+    let id = child.id();
+    children().push(id);
+
+    // Wait for the command, asking it to stop if the daemon is shutting down
+    let mut signalled = false;
+    let result = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) => {}
+            Err(e) => break Err(e.into()),
+        }
+        if should_stop() && !signalled {
+            signal_group(id, libc::SIGTERM);
+            signalled = true;
+        }
+        thread::sleep(TICK);
+    };
+
+    // The child has been reaped, so its process group must not be signalled any more
+    children().retain(|&child| child != id);
+    result
+}
+
+/// Exit the process if it has not shut down in time after a termination signal
+///
+/// Any child processes still running at the deadline are killed first
+pub fn spawn_shutdown_watchdog() -> Result<()> {
+    thread::Builder::new()
+        .name("shutdown-watchdog".to_string())
+        .spawn(|| {
+            // Wait for a termination signal, then for the shutdown deadline
+            while !is_terminating() {
+                thread::sleep(TICK);
+            }
+            let timeout = Duration::from_millis(SHUTDOWN_TIMEOUT.load(Ordering::Relaxed));
+            thread::sleep(timeout);
+
+            // Still running, so stop everything
+            for &id in children().iter() {
+                warn!("Killing process group {} at shutdown deadline", id);
+                signal_group(id, libc::SIGKILL);
+            }
+            error!(
+                "Did not shut down within {}, exiting",
+                humantime::format_duration(timeout)
+            );
+            std::process::exit(1);
+        })?;
+
+    Ok(())
+}
+
+/// The list of running child process groups, recovering from a poisoned lock
+fn children() -> std::sync::MutexGuard<'static, Vec<u32>> {
+    CHILDREN.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Send a signal to a child's process group
+fn signal_group(id: u32, signal: libc::c_int) {
+    // SAFETY: kill has no memory safety requirements, a stale group id just fails with ESRCH
+    unsafe {
+        libc::kill(-(id as libc::pid_t), signal);
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+/// Random bytes, good enough for probe nonces and spreading out retries but not for cryptography
+///
+/// Each RandomState is seeded randomly, which avoids pulling in a random number generator
+pub fn bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    for chunk in bytes.chunks_mut(8) {
+        let random = RandomState::new().build_hasher().finish().to_le_bytes();
+        chunk.copy_from_slice(&random[..chunk.len()]);
+    }
+    bytes
+}
+
+/// A random number in the range [0, 1)
+pub fn unit() -> f64 {
+    (u64::from_le_bytes(bytes()) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_every_byte() {
+        // Bytes past the first hash are filled too, so they are not all zero
+        let bytes = (0..10).map(|_| bytes::<20>()).collect::<Vec<_>>();
+        assert!(bytes.iter().any(|bytes| bytes[16..] != [0; 4]));
+        assert!(bytes.iter().any(|other| *other != bytes[0]));
+    }
+
+    #[test]
+    fn units_are_in_range() {
+        assert!(
+            (0..1000)
+                .map(|_| unit())
+                .all(|unit| (0.0..1.0).contains(&unit))
+        );
+    }
+}
@@ -1,55 +1,93 @@
 use std::{
-    fmt::Debug,
-    net::{SocketAddr, TcpStream, ToSocketAddrs},
-    path::{Path, PathBuf},
-    process::Command,
+    iter, mem,
+    path::PathBuf,
     sync::{
-        Arc,
+        Arc, Mutex,
         atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
     },
-    thread::sleep,
-    time::Duration,
+    thread::scope,
+    time::{Duration, Instant},
 };
 
 use anyhow::Result;
 
-use signal_hook::flag::register;
-use tracing::{debug, error, info, instrument};
+use signal_hook::flag::{register, register_conditional_shutdown};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::{
+    config::Config,
+    control::{self, Request, Response},
+    metrics, network, process,
+    server::{Server, new_server},
+    share::{self, ShareState, expand_home, format_deadline},
+    status::Status,
+};
+
+/// How long to wait for a burst of network changes to settle before checking servers
+const NETWORK_SETTLE_DELAY: Duration = Duration::from_millis(250);
+
+/// The exit code of a one-shot command when a server could not be reached
+pub const EXIT_UNREACHABLE: i32 = 2;
+
+/// The exit code of a one-shot command when a share is not mounted and healthy
+pub const EXIT_NOT_MOUNTED: i32 = 3;
+
+/// Something the main loop is woken up for
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// The network configuration changed
+    NetworkChanged,
+    /// A control request to handle, with where to send the response
+    Request(Request, Sender<Response>),
+}
+
+/// How long probing and remounting are paused for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pause {
+    /// Until resumed on request
+    Indefinitely,
+    /// Until the given time, or until resumed on request
+    Until(Instant),
+}
 
 /// Struct representing the Remounter
 pub struct Remounter {
-    server: String,
-    socket_address: SocketAddr,
-    smb_shares: Vec<PathBuf>,
-    post_mount_script: Option<String>,
+    config: Config,
+    config_path: Option<PathBuf>,
+    servers: Vec<Server>,
+    status: Arc<Mutex<Status>>,
+    control_socket: Option<PathBuf>,
+    paused: Option<Pause>,
+    had_route: bool,
 }
 
 /// Create a new Remounter instance
-#[instrument]
-pub fn new_remounter<S, I, P>(
-    server: S,
-    smb_shares: I,
-    post_mount_script: Option<String>,
-) -> Result<Remounter>
-where
-    S: Into<String> + Debug,
-    I: IntoIterator<Item = P> + Debug,
-    P: Into<PathBuf>,
-{
-    // Resolve the server address to a SocketAddr
-    let server = server.into();
-    let socket_address = format!("{}:445", server);
-    let socket_address = socket_address
-        .to_socket_addrs()?
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("Could not resolve to any addresses"))?;
+///
+/// If `config_path` is given the configuration is re-read from it on SIGHUP.
+/// A `control_socket` given here wins over the one in the configuration
+#[instrument(skip(config))]
+pub fn new_remounter(
+    config: Config,
+    config_path: Option<PathBuf>,
+    control_socket: Option<PathBuf>,
+) -> Result<Remounter> {
+    // Create a Server instance for each configured server
+    let servers = config
+        .servers
+        .iter()
+        .map(|server| new_server(server, &config))
+        .collect::<Result<Vec<_>>>()?;
 
     // Create the Remounter instance
     let remounter = Remounter {
-        server,
-        socket_address,
-        smb_shares: smb_shares.into_iter().map(Into::into).collect(),
-        post_mount_script,
+        config,
+        config_path,
+        servers,
+        status: Arc::default(),
+        control_socket,
+        paused: None,
+        had_route: true,
     };
 
     // Return the Remounter instance
@@ -57,157 +95,566 @@ where
 }
 
 impl Remounter {
+    /// The servers being monitored
+    pub fn servers(&self) -> &[Server] {
+        &self.servers
+    }
+
     /// Run the remounter
     #[instrument(skip(self))]
-    pub fn run(&self) -> Result<()> {
+    pub fn run(&mut self) -> Result<()> {
+        // Network changes and control requests all wake up the main loop, holding on to a
+        // sender keeps the channel open when neither the watcher nor the socket is available
+        let (sender, events) = mpsc::channel();
+        network::watch(sender.clone(), Event::NetworkChanged);
+
+        // Answer control requests, carrying on without them if the socket cannot be set up
+        self.publish_status();
+        let socket_path = self.socket_path(&self.config);
+        let _socket = control::listen(&socket_path, Arc::clone(&self.status), sender.clone())
+            .inspect_err(|e| warn!("Control socket unavailable: {:#}", e))
+            .ok();
+
+        // Serve metrics if requested, carrying on without them if the address is in use
+        if let Some(address) = self.config.metrics_address()?
+            && let Err(e) = metrics::listen(address)
+        {
+            warn!("Metrics unavailable: {:#}", e);
+        }
+
         // Run the connection check loop
-        self.check_connection()?;
+        self.check_connection(&events)?;
+        drop(sender);
+
+        // Clean up the shares this daemon mounted, if requested
+        if self.config.unmount_on_exit() {
+            process::start_cleanup();
+            info!("Unmounting shares before exiting");
+            self.servers.iter_mut().for_each(Server::unmount_shares);
+        }
 
         // If we exit the loop due to a termination signal, return Ok(())
         Ok(())
     }
 
-    /// Check if the server is reachable
+    /// Probe every server once and report on its shares without mounting anything
     #[instrument(skip(self))]
-    fn is_up(&self, address: &SocketAddr) -> bool {
-        // Attempt to connect to the address with a timeout of 10 seconds
-        TcpStream::connect_timeout(address, Duration::from_secs(10)).is_ok()
+    pub fn check_once(&mut self) -> Result<Status> {
+        self.handle_termination()?;
+
+        // Probe every server, then look at each share whether or not its server is up
+        let up = self.probe_due_servers();
+        if process::is_terminating() {
+            return Err(anyhow::anyhow!("Interrupted by a termination signal"));
+        }
+        for (server, is_up) in self.servers.iter_mut().zip(up) {
+            server.inspect(is_up.unwrap_or(false));
+        }
+
+        Ok(self.snapshot())
     }
 
-    /// Check the connection status and trigger remounting when the connection is restored
+    /// Probe every server once and mount any shares that need it
     #[instrument(skip(self))]
-    fn check_connection(&self) -> Result<()> {
-        // Register signal handlers for SIGTERM and SIGINT
-        let term = Arc::new(AtomicBool::new(false));
-        register(signal_hook::consts::SIGTERM, Arc::clone(&term))?;
-        register(signal_hook::consts::SIGINT, Arc::clone(&term))?;
+    pub fn mount_once(&mut self) -> Result<Status> {
+        self.handle_termination()?;
+
+        // Probe every server, then mount the shares of those that are up
+        let up = self.probe_due_servers();
+        if process::is_terminating() {
+            return Err(anyhow::anyhow!("Interrupted by a termination signal"));
+        }
+        for (server, is_up) in self.servers.iter_mut().zip(up) {
+            server.update(is_up.unwrap_or(false))?;
+        }
+
+        Ok(self.snapshot())
+    }
+
+    /// The exit code for a one-shot command, reflecting the state of every server and share
+    pub fn exit_code(&self) -> i32 {
+        if !self.servers.iter().all(Server::is_reachable) {
+            EXIT_UNREACHABLE
+        } else if !self
+            .servers
+            .iter()
+            .flat_map(Server::shares)
+            .all(|share| share.state == ShareState::Mounted)
+        {
+            EXIT_NOT_MOUNTED
+        } else {
+            0
+        }
+    }
+
+    /// Stop cleanly on SIGTERM and SIGINT, within the shutdown timeout
+    fn handle_termination(&self) -> Result<()> {
+        // Register signal handlers for SIGTERM and SIGINT, a second signal exits immediately
+        let term = process::terminating_flag();
+        for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+            register_conditional_shutdown(signal, 1, Arc::clone(&term))?;
+            register(signal, Arc::clone(&term))?;
+        }
+
+        // Make sure shutdown finishes within the deadline even if something hangs
+        process::set_shutdown_timeout(self.config.shutdown_timeout());
+        process::spawn_shutdown_watchdog()?;
+
+        Ok(())
+    }
+
+    /// Check the connection status and trigger remounting when the connection is restored
+    #[instrument(skip(self, events))]
+    fn check_connection(&mut self, events: &Receiver<Event>) -> Result<()> {
+        self.handle_termination()?;
 
-        // Set initial state of was_up to false
-        let mut was_up = false;
+        // Register a signal handler for SIGHUP to reload the configuration
+        let reload = Arc::new(AtomicBool::new(false));
+        register(signal_hook::consts::SIGHUP, Arc::clone(&reload))?;
 
         // Main loop to check connection status
-        while !term.load(Ordering::Relaxed) {
-            // Check if the socket is up or down and handle state changes
-            if self.is_up(&self.socket_address) {
-                if !was_up {
-                    // Update state to indicate the connection is now up
-                    was_up = true;
-
-                    // Log that the connection is back up
-                    info!(
-                        "{}:{} is up, attempting to remount...",
-                        self.server,
-                        self.socket_address.port()
-                    );
-
-                    // Attempt to remount drives when the connection is back up
-                    match self.remount_shares() {
-                        Ok(_) => info!("Remount successful"),
-                        Err(e) => {
-                            // Log the remount failure
-                            error!("Remount failed: {}", e);
-
-                            // Continue to the next iteration of the loop without executing the post-mount script
-                            continue;
-                        }
-                    }
+        while !process::is_terminating() {
+            // Reload the configuration if requested
+            if reload.swap(false, Ordering::Relaxed) {
+                self.reload();
+            }
 
-                    // If a post-mount script is provided, execute it
-                    if let Some(script) = &self.post_mount_script {
-                        info!("Executing post-mount script: {}", script);
-                        let status = Command::new("sh").arg("-c").arg(script).status()?;
-                        if !status.success() {
-                            error!("Post-mount script failed with status: {}", status);
-                        }
-                    }
-                }
-            } else if was_up {
-                // Log that the connection is down
-                info!(
-                    "{}:{} is down, will attempt to remount when it is back up",
-                    self.server,
-                    self.socket_address.port()
-                );
+            // End a timed pause once it has run out
+            if let Some(Pause::Until(until)) = self.paused
+                && Instant::now() >= until
+            {
+                info!("Pause is over, resuming");
+                self.resume();
+            }
 
-                // Update state to indicate the connection is now down
-                was_up = false;
+            // Probe and remount unless paused on request
+            if self.paused.is_none() {
+                self.poll_servers()?;
             }
 
-            // Sleep for 1 second before the next check
-            sleep(Duration::from_secs(1));
+            // Make the latest state available to control requests
+            self.publish_status();
+
+            // Sleep until the next server is due, or the pause ends, waking at least every second
+            let now = Instant::now();
+            let wait = match self.paused {
+                Some(Pause::Until(until)) => until.saturating_duration_since(now),
+                Some(Pause::Indefinitely) => Duration::MAX,
+                None => self
+                    .servers
+                    .iter()
+                    .map(|server| server.next_poll().saturating_duration_since(now))
+                    .min()
+                    .unwrap_or_default(),
+            };
+            let wait = wait.min(Duration::from_secs(1));
+            if let Ok(event) = process::recv_timeout(events, wait) {
+                self.handle_events(event, events);
+            }
         }
 
         info!("Termination signal received, exiting...");
         Ok(())
     }
 
-    /// Function to handle remounting a single share
-    #[instrument(skip(self))]
-    fn remount(&self, smb_share: &Path) -> Result<()> {
-        // Convert the share name to a string (strip any leading slash)
-        let share_name = smb_share
-            .to_str()
-            .map(|share| share.trim_start_matches('/'))
-            .filter(|share| !share.is_empty())
-            .ok_or_else(|| anyhow::anyhow!("Invalid share name"))?;
-
-        let local_share_path = Path::new("/Volumes").join(share_name);
-
-        // Skip remounting if the share is mounted and healthy
-        if local_share_path.exists() {
-            let marker = local_share_path.join(".smb_remounter");
-            if marker.exists() {
-                debug!(
-                    "Share {} is mounted and healthy, skipping remount",
-                    local_share_path.display()
-                );
-                return Ok(());
+    /// Probe every server that is due and handle any state changes
+    fn poll_servers(&mut self) -> Result<()> {
+        // Without a route there is no point probing, so servers that need the network are down
+        let now = Instant::now();
+        let has_route = network::has_route();
+        if has_route != self.had_route {
+            if has_route {
+                info!("Network route available, resuming probes");
+            } else {
+                info!("No network route, pausing probes");
+            }
+            self.had_route = has_route;
+        }
+        if !has_route {
+            for server in self
+                .servers
+                .iter_mut()
+                .filter(|server| server.next_poll() <= now && server.needs_network())
+            {
+                server.update(false)?;
             }
-            return Err(anyhow::anyhow!(
-                "Share {} exists but is not healthy, not mounting",
-                local_share_path.display()
-            ));
         }
 
-        // Construct the mount command
-        let mount_command = format!(
-            "osascript -e 'mount volume \"smb://{}/{}\"'",
-            self.server, share_name,
-        );
-
-        // Log the mount command for info
-        debug!("Executing mount command: {}", mount_command);
+        // Check all servers that are due
+        let up = self.probe_due_servers();
 
-        // Execute the mount command using AppleScript
-        let status = Command::new("sh").arg("-c").arg(mount_command).status()?;
+        // Stop without acting on probes that were cut short by a termination signal
+        if process::is_terminating() {
+            return Ok(());
+        }
 
-        // Check if the command was successful, return an error if not
-        if !status.success() {
-            return Err(anyhow::anyhow!("Failed to execute mount command"));
+        // Handle any state changes for each server that was checked
+        for (server, is_up) in self.servers.iter_mut().zip(up) {
+            if let Some(is_up) = is_up {
+                server.update(is_up)?;
+            }
         }
 
         Ok(())
     }
 
-    /// Remount all shares
+    /// Probe every server that is due, returning None for those that were not
+    ///
+    /// Servers are probed in parallel so one unreachable server does not delay the others
+    fn probe_due_servers(&mut self) -> Vec<Option<bool>> {
+        let now = Instant::now();
+        scope(|scope| {
+            self.servers
+                .iter_mut()
+                .map(|server| {
+                    (server.next_poll() <= now).then(|| scope.spawn(move || server.is_up()))
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.map(|handle| handle.join().unwrap_or(false)))
+                .collect()
+        })
+    }
+
+    /// Handle an event and any others that arrive while settling
+    fn handle_events(&mut self, event: Event, events: &Receiver<Event>) {
+        // Let a burst of network changes settle before checking servers
+        if matches!(event, Event::NetworkChanged) {
+            process::sleep(NETWORK_SETTLE_DELAY);
+        }
+
+        let mut network_changed = false;
+        for event in iter::once(event).chain(events.try_iter()) {
+            match event {
+                Event::NetworkChanged => network_changed = true,
+                Event::Request(request, reply) => {
+                    let response = self.handle_request(request);
+                    self.publish_status();
+                    let _ = reply.send(response);
+                }
+            }
+        }
+
+        // Check every server straight away after a network change
+        if network_changed {
+            debug!("Network changed, checking all servers");
+            self.servers.iter_mut().for_each(Server::poll_now);
+        }
+    }
+
+    /// Act on a control request
     #[instrument(skip(self))]
-    fn remount_shares(&self) -> Result<()> {
-        // Collect errors from remount attempts
-        let errors = self
-            .smb_shares
+    fn handle_request(&mut self, request: Request) -> Response {
+        let result = match request {
+            Request::Status => return Response::Status(self.snapshot()),
+            Request::Remount { share } => self
+                .find_share(&share)
+                .and_then(|(server, index)| self.servers[server].remount_share(index)),
+            Request::Unmount { share } => self
+                .find_share(&share)
+                .and_then(|(server, index)| self.servers[server].unmount_share(index)),
+            Request::Pause { duration } => self.pause(duration),
+            Request::Resume => match self.paused {
+                Some(_) => {
+                    info!("Resuming on request");
+                    self.resume();
+                    Ok("Resumed".to_string())
+                }
+                None => Ok("Not paused".to_string()),
+            },
+        };
+        match result {
+            Ok(message) => Response::Ok(message),
+            Err(e) => Response::Error(format!("{:#}", e)),
+        }
+    }
+
+    /// Find a share given as `name` or `host/name`, returning the server and share indices
+    fn find_share(&self, name: &str) -> Result<(usize, usize)> {
+        // Look for the share on every server, or on the named one
+        let (host, name) = match name.rsplit_once('/') {
+            Some((host, name)) => (Some(host), name),
+            None => (None, name),
+        };
+        let matches = self
+            .servers
             .iter()
-            .filter_map(|share| self.remount(share).err())
+            .enumerate()
+            .filter(|(_, server)| host.is_none_or(|host| server.host() == host))
+            .flat_map(|(server_index, server)| {
+                server
+                    .shares()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, share)| share.name == name)
+                    .map(move |(share_index, _)| (server_index, share_index))
+            })
             .collect::<Vec<_>>();
 
-        // If there were any errors, log them and return an error
-        if !errors.is_empty() {
-            for error in errors {
-                error!("Error remounting share: {}", error);
+        match matches.as_slice() {
+            [found] => Ok(*found),
+            [] => Err(anyhow::anyhow!("No share named {} is monitored", name)),
+            _ => Err(anyhow::anyhow!(
+                "More than one server has a share named {}, give it as host/{}",
+                name,
+                name
+            )),
+        }
+    }
+
+    /// The control socket to listen on under the given configuration
+    fn socket_path(&self, config: &Config) -> PathBuf {
+        match (&self.control_socket, &config.control_socket) {
+            (Some(control_socket), _) => control_socket.clone(),
+            (None, Some(control_socket)) => expand_home(control_socket),
+            (None, None) => control::default_socket_path(),
+        }
+    }
+
+    /// Stop probing and remounting, returning a description of the pause
+    fn pause(&mut self, duration: Option<Duration>) -> Result<String> {
+        let message = match duration {
+            Some(duration) => {
+                // Refuse pauses that end too far in the future to keep track of
+                let until = Instant::now()
+                    .checked_add(duration)
+                    .filter(|_| share::deadline(duration).is_some())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Cannot pause for {}, that is too long",
+                            humantime::format_duration(duration)
+                        )
+                    })?;
+                self.paused = Some(Pause::Until(until));
+                format!("Paused until {}", format_deadline(duration))
             }
-            return Err(anyhow::anyhow!("One or more shares failed to remount"));
+            None => {
+                self.paused = Some(Pause::Indefinitely);
+                "Paused until resumed".to_string()
+            }
+        };
+        info!("{} on request", message);
+        Ok(message)
+    }
+
+    /// Start probing and remounting again, checking every server straight away
+    fn resume(&mut self) {
+        self.paused = None;
+        self.servers.iter_mut().for_each(Server::poll_now);
+    }
+
+    /// Update the status snapshot served over the control socket
+    fn publish_status(&self) {
+        metrics::update(&self.servers);
+        let status = self.snapshot();
+        *self.status.lock().unwrap_or_else(|e| e.into_inner()) = status;
+    }
+
+    /// A snapshot of the state of every server and share
+    fn snapshot(&self) -> Status {
+        Status {
+            paused: self.paused.is_some(),
+            paused_until: match self.paused {
+                Some(Pause::Until(until)) => Some(format_deadline(
+                    until.saturating_duration_since(Instant::now()),
+                )),
+                _ => None,
+            },
+            servers: self.servers.iter().map(Server::status).collect(),
         }
+    }
 
-        // If all shares were remounted successfully, return Ok(())
-        Ok(())
+    /// Re-read the configuration file and update the set of monitored servers and shares
+    ///
+    /// Servers whose configuration is unchanged are left alone, so reloading
+    /// does not trigger any unnecessary remounts
+    #[instrument(skip(self))]
+    fn reload(&mut self) {
+        // Reloading is only possible when running from a configuration file
+        let Some(path) = &self.config_path else {
+            warn!("SIGHUP received but no configuration file is in use, ignoring");
+            return;
+        };
+        info!(
+            "SIGHUP received, reloading configuration from {}",
+            path.display()
+        );
+
+        // Keep the current configuration if the new one cannot be loaded
+        let config = match Config::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                error!(
+                    "Error reloading configuration, keeping the current one: {:#}",
+                    e
+                );
+                return;
+            }
+        };
+
+        // Build the new list of servers, reusing existing ones where possible
+        let mut old_servers = mem::take(&mut self.servers);
+        for server_config in &config.servers {
+            // Find the server with the same host in the old configuration
+            let old = old_servers
+                .iter()
+                .position(|server| server.host() == server_config.host)
+                .map(|index| old_servers.remove(index));
+            let old_config = self
+                .config
+                .servers
+                .iter()
+                .find(|server| server.host == server_config.host);
+
+            match (old, old_config) {
+                // Unchanged servers keep running as they are
+                (Some(old), Some(old_config))
+                    if !old_config.differs(&self.config, server_config, &config) =>
+                {
+                    self.servers.push(old);
+                }
+                // Changed or new servers are recreated, taking over any existing state
+                (old, _) => match new_server(server_config, &config) {
+                    Ok(mut server) => {
+                        match old {
+                            Some(old) => {
+                                info!("Configuration changed for {}", server.host());
+                                server.replace(old);
+                            }
+                            None => info!("Now monitoring SMB shares on {}", server.host()),
+                        }
+                        self.servers.push(server);
+                    }
+                    Err(e) => {
+                        error!("Error creating server {}: {}", server_config.host, e);
+                        if let Some(old) = old {
+                            self.servers.push(old);
+                        }
+                    }
+                },
+            }
+        }
+
+        // Anything left over has been removed from the configuration
+        for server in old_servers {
+            info!("No longer monitoring SMB shares on {}", server.host());
+        }
+
+        // The control socket and metrics listener are only set up at startup
+        if self.socket_path(&config) != self.socket_path(&self.config) {
+            warn!(
+                "The control socket changed to {}, restart remounter to listen there",
+                self.socket_path(&config).display()
+            );
+        }
+        if config.metrics_address != self.config.metrics_address {
+            warn!("The metrics address changed, restart remounter to serve metrics there");
+        }
+
+        // Remember the new configuration for the next reload
+        process::set_shutdown_timeout(config.shutdown_timeout());
+        self.config = config;
+        info!("Configuration reloaded");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread};
+
+    use super::*;
+
+    /// Two servers, with extra settings and shares for the second one
+    fn config(global: &str, extra_shares: &str) -> String {
+        format!(
+            r#"
+            {}
+
+            [[server]]
+            host = "127.0.0.1"
+            mount_root = "/nonexistent/remounter/a"
+            share = [{{ name = "Media" }}]
+
+            [[server]]
+            host = "127.0.0.2"
+            mount_root = "/nonexistent/remounter/b"
+            share = [{{ name = "Backups" }}{}]
+            "#,
+            global, extra_shares
+        )
+    }
+
+    /// The states of a server's shares
+    fn states(server: &Server) -> Vec<ShareState> {
+        server.shares().iter().map(|share| share.state).collect()
+    }
+
+    #[test]
+    fn reload_only_rebuilds_changed_servers() {
+        let path =
+            std::env::temp_dir().join(format!("remounter-{}-reload.toml", std::process::id()));
+        fs::write(&path, config("", "")).unwrap();
+        let mut remounter =
+            new_remounter(Config::load(&path).unwrap(), Some(path.clone()), None).unwrap();
+
+        // Learn something about every server, then let time move on
+        for server in &mut remounter.servers {
+            server.inspect(true);
+        }
+        let polls = |remounter: &Remounter| {
+            remounter
+                .servers
+                .iter()
+                .map(Server::next_poll)
+                .collect::<Vec<_>>()
+        };
+        let started = polls(&remounter);
+        thread::sleep(Duration::from_millis(5));
+
+        // Adding a share to one server leaves the other one running as it was
+        fs::write(&path, config("", r#", { name = "Archive" }"#)).unwrap();
+        remounter.reload();
+        let reloaded = polls(&remounter);
+        assert_eq!(reloaded[0], started[0]);
+        assert!(remounter.servers[0].is_reachable());
+        assert_eq!(states(&remounter.servers[0]), [ShareState::Unmounted]);
+
+        // The changed server is rebuilt, taking over the state of the shares it already had
+        assert_ne!(reloaded[1], started[1]);
+        assert!(remounter.servers[1].is_reachable());
+        assert_eq!(
+            states(&remounter.servers[1]),
+            [ShareState::Unmounted, ShareState::Unknown]
+        );
+        thread::sleep(Duration::from_millis(5));
+
+        // A global change rebuilds every server it applies to
+        fs::write(
+            &path,
+            config("[retry]\nmax_attempts = 3", r#", { name = "Archive" }"#),
+        )
+        .unwrap();
+        remounter.reload();
+        let rebuilt = polls(&remounter);
+        for (server, (rebuilt, reloaded)) in
+            remounter.servers.iter().zip(rebuilt.iter().zip(reloaded))
+        {
+            assert_ne!(*rebuilt, reloaded);
+            assert!(
+                server
+                    .shares()
+                    .iter()
+                    .all(|share| share.retry.max_attempts == Some(3))
+            );
+            assert_eq!(states(server)[0], ShareState::Unmounted);
+        }
+
+        // A configuration that does not load leaves everything as it was
+        fs::write(&path, "not toml").unwrap();
+        remounter.reload();
+        assert_eq!(polls(&remounter), rebuilt);
+        assert_eq!(remounter.servers[1].shares().len(), 2);
+
+        fs::remove_file(path).unwrap();
     }
 }
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::random;
+
+/// The longest delay allowed between attempts, so deadlines always stay representable
+const MAX_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How failed mounts of a share are retried
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// The delay before the first retry
+    pub initial_delay: Duration,
+    /// The factor the delay grows by after each failed attempt
+    pub multiplier: f64,
+    /// The longest delay between attempts
+    pub max_delay: Duration,
+    /// The number of attempts before giving up, or None to retry forever
+    pub max_attempts: Option<u32>,
+    /// The fraction of the delay to randomly add or subtract
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(5),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(300),
+            max_attempts: None,
+            jitter: 0.1,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Check that the policy values are within range
+    pub fn validate(&self) -> Result<()> {
+        if self.initial_delay.is_zero() {
+            return Err(anyhow::anyhow!("initial_delay must be greater than zero"));
+        }
+        if !(self.multiplier >= 1.0 && self.multiplier.is_finite()) {
+            return Err(anyhow::anyhow!("multiplier must be at least 1.0"));
+        }
+        if self.max_delay > MAX_DELAY {
+            return Err(anyhow::anyhow!(
+                "max_delay must not be longer than {}",
+                humantime::format_duration(MAX_DELAY)
+            ));
+        }
+        if self.max_delay < self.initial_delay {
+            return Err(anyhow::anyhow!(
+                "max_delay must not be less than initial_delay"
+            ));
+        }
+        if self.max_attempts == Some(0) {
+            return Err(anyhow::anyhow!("max_attempts must be greater than zero"));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(anyhow::anyhow!("jitter must be between 0.0 and 1.0"));
+        }
+        Ok(())
+    }
+
+    /// The delay before the next attempt after the given number of failed attempts
+    ///
+    /// Returns None once the maximum number of attempts has been reached
+    pub fn next_delay(&self, attempts: u32) -> Option<Duration> {
+        // Give up once the maximum number of attempts has been made
+        if self.max_attempts.is_some_and(|max| attempts >= max) {
+            return None;
+        }
+
+        // Grow the delay exponentially, capped at the maximum delay
+        let exponent = attempts.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        let delay = delay.min(self.max_delay.as_secs_f64());
+
+        // Spread retries out by adding or subtracting a random fraction of the delay
+        let jitter = delay * self.jitter * (random::unit() * 2.0 - 1.0);
+
+        Some(Duration::from_secs_f64((delay + jitter).max(0.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A policy without jitter, so delays are exact
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            initial_delay: Duration::from_secs(5),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(60),
+            max_attempts: None,
+            jitter: 0.0,
+        }
+    }
+
+    #[test]
+    fn grows_delays_up_to_the_maximum() {
+        let delays = (1..=6)
+            .map(|attempts| policy().next_delay(attempts).unwrap().as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [5, 10, 20, 40, 60, 60]);
+
+        // Huge attempt counts do not overflow
+        assert_eq!(policy().next_delay(u32::MAX), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let policy = RetryPolicy {
+            max_attempts: Some(3),
+            ..policy()
+        };
+        assert_eq!(policy.next_delay(2), Some(Duration::from_secs(10)));
+        assert_eq!(policy.next_delay(3), None);
+        assert_eq!(policy.next_delay(4), None);
+    }
+
+    #[test]
+    fn keeps_jitter_within_bounds() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..policy()
+        };
+        let delays = (0..200)
+            .map(|_| policy.next_delay(2).unwrap())
+            .collect::<Vec<_>>();
+        assert!(
+            delays.iter().all(|delay| {
+                (Duration::from_secs(5)..=Duration::from_secs(15)).contains(delay)
+            })
+        );
+        assert!(delays.iter().any(|&delay| delay != delays[0]));
+    }
+}
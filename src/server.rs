@@ -0,0 +1,696 @@
+use std::{
+    net::SocketAddr,
+    process::Command,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use tracing::{Span, debug, error, info, instrument, warn};
+
+use crate::{
+    backend::{MountBackend, new_backend},
+    config::{Config, ServerConfig},
+    metrics, net, process,
+    share::{Share, ShareState, expand_home},
+    smb::{self, Negotiated, ServerGuid},
+    status::ServerStatus,
+};
+
+/// How a server's reachability is checked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeKind {
+    /// Only check that a TCP connection can be established
+    Tcp,
+    /// Send an SMB2 NEGOTIATE request and validate the response
+    #[default]
+    Smb,
+}
+
+/// How often the host name of a reachable server is resolved again
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often a server reached through a fallback host checks whether a higher priority host is back
+const FAIL_BACK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// One of the names a server can be reached by, with the addresses it last resolved to
+#[derive(Debug)]
+struct Endpoint {
+    host: String,
+    addresses: Vec<SocketAddr>,
+    resolved_at: Instant,
+}
+
+impl Endpoint {
+    /// Resolve a host name, treating failure as the host being down until it resolves
+    fn new(host: &str, port: u16) -> Self {
+        let addresses = net::resolve(host, port).unwrap_or_else(|e| {
+            warn!("{:#}, will keep trying while waiting for the network", e);
+            Vec::new()
+        });
+        Endpoint {
+            host: host.to_string(),
+            addresses,
+            resolved_at: Instant::now(),
+        }
+    }
+
+    /// Resolve the host name again, logging any change of address
+    ///
+    /// The previous addresses are kept if the host name cannot be resolved
+    fn resolve(&mut self, port: u16) {
+        self.resolved_at = Instant::now();
+        match net::resolve(&self.host, port) {
+            Ok(addresses) if self.addresses.is_empty() => {
+                info!(
+                    "{} resolves to {}",
+                    self.host,
+                    net::format_addresses(&addresses)
+                );
+                self.addresses = addresses;
+            }
+            Ok(addresses) if addresses != self.addresses => {
+                info!(
+                    "{} now resolves to {} (was {})",
+                    self.host,
+                    net::format_addresses(&addresses),
+                    net::format_addresses(&self.addresses)
+                );
+                self.addresses = addresses;
+            }
+            Ok(_) => {}
+            Err(e) => debug!("Could not resolve {}: {:#}", self.host, e),
+        }
+    }
+}
+
+/// A monitored server and its shares
+#[derive(Debug)]
+pub struct Server {
+    host: String,
+    port: u16,
+    endpoints: Vec<Endpoint>,
+    active: usize,
+    fail_back_at: Instant,
+    address: Option<SocketAddr>,
+    smb_shares: Vec<Share>,
+    post_mount_script: Option<String>,
+    backend: Arc<dyn MountBackend>,
+    probe: ProbeKind,
+    probe_timeout: Duration,
+    poll_interval: Duration,
+    next_poll: Instant,
+    server_guid: Option<ServerGuid>,
+    was_up: bool,
+    needs_post_mount: bool,
+}
+
+/// Create a new Server instance from its configuration
+pub fn new_server(config: &ServerConfig, global: &Config) -> Result<Server> {
+    let backend = new_backend(config.backend_kind(global), config.cifs.clone());
+    new_server_with_backend(config, global, backend)
+}
+
+/// Create a new Server instance that mounts its shares with the given backend
+#[instrument(skip(config, global, backend), fields(host = %config.host))]
+pub fn new_server_with_backend(
+    config: &ServerConfig,
+    global: &Config,
+    backend: Arc<dyn MountBackend>,
+) -> Result<Server> {
+    // Resolve the addresses of every host the server is known by
+    let port = config.port(global);
+    let endpoints = config
+        .candidate_hosts()
+        .iter()
+        .map(|host| Endpoint::new(host, port))
+        .collect();
+
+    // Work out where shares are mounted
+    let mount_root = match config.mount_root.as_ref().or(global.mount_root.as_ref()) {
+        Some(mount_root) => expand_home(mount_root),
+        None => backend.mount_root().to_path_buf(),
+    };
+
+    // Create the Server instance
+    let server = Server {
+        host: config.host.clone(),
+        port,
+        endpoints,
+        active: 0,
+        fail_back_at: Instant::now(),
+        address: None,
+        smb_shares: config
+            .shares
+            .iter()
+            .map(|share| {
+                Share::from_config(
+                    share,
+                    &mount_root,
+                    global.retry_policy(config, share),
+                    global.health_check(config, share),
+                )
+            })
+            .collect(),
+        post_mount_script: config.post_mount_script.clone(),
+        backend,
+        probe: config.probe_kind(global),
+        probe_timeout: config.probe_timeout(global),
+        poll_interval: config.poll_interval(global),
+        next_poll: Instant::now(),
+        server_guid: None,
+        was_up: false,
+        needs_post_mount: false,
+    };
+
+    // Return the Server instance
+    Ok(server)
+}
+
+impl Server {
+    /// The hostname of the server
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The shares on the server
+    pub fn shares(&self) -> &[Share] {
+        &self.smb_shares
+    }
+
+    /// The script run after remounting, if any
+    pub fn post_mount_script(&self) -> Option<&str> {
+        self.post_mount_script.as_deref()
+    }
+
+    /// Whether the server answered the last reachability probe
+    pub fn is_reachable(&self) -> bool {
+        self.was_up
+    }
+
+    /// When this server is next due to be probed
+    pub fn next_poll(&self) -> Instant {
+        self.next_poll
+    }
+
+    /// A snapshot of the server's state for status reports
+    pub fn status(&self) -> ServerStatus {
+        ServerStatus {
+            host: self.host.clone(),
+            reachable: self.was_up,
+            reached_through: self.endpoints[self.active].host.clone(),
+            address: self.address.map(|address| address.to_string()),
+            server_guid: self.server_guid.map(|guid| guid.to_string()),
+            shares: self.smb_shares.iter().map(Share::status).collect(),
+        }
+    }
+
+    /// Probe this server on the next iteration, e.g. because the network changed
+    pub fn poll_now(&mut self) {
+        self.next_poll = Instant::now();
+    }
+
+    /// Check whether reaching this server needs a network route
+    ///
+    /// Servers only reached through loopback addresses, such as an SSH tunnel,
+    /// keep being probed while there is no network
+    pub fn needs_network(&self) -> bool {
+        !self.endpoints.iter().all(|endpoint| {
+            !endpoint.addresses.is_empty()
+                && endpoint
+                    .addresses
+                    .iter()
+                    .all(|address| address.ip().is_loopback())
+        })
+    }
+
+    /// Take over the state of the server this one replaces after a configuration reload
+    ///
+    /// Shares that are unchanged keep their state, new shares are checked on the next update
+    #[instrument(skip(self, old), fields(host = %self.host))]
+    pub fn replace(&mut self, mut old: Server) {
+        // Keep the connection state so unchanged shares are not remounted
+        self.was_up = old.was_up;
+        self.needs_post_mount = old.needs_post_mount;
+        self.server_guid = old.server_guid;
+        self.address = old.address;
+
+        // Log any shares that are no longer monitored
+        for share in old
+            .smb_shares
+            .iter()
+            .filter(|old| !self.smb_shares.iter().any(|share| share.is_same(old)))
+        {
+            info!(
+                "No longer monitoring {} at {}",
+                share.name,
+                share.mount_point.display()
+            );
+        }
+
+        // Carry over the state of unchanged shares
+        for share in &mut self.smb_shares {
+            match old.smb_shares.iter_mut().find(|old| old.is_same(share)) {
+                Some(old) => share.take_state(old),
+                None => info!(
+                    "Now monitoring {} at {}",
+                    share.name,
+                    share.mount_point.display()
+                ),
+            }
+        }
+    }
+
+    /// Check if the server is reachable, and with the SMB probe that it is answering SMB
+    ///
+    /// Each of the server's hosts is tried in priority order and the first one
+    /// that answers becomes the one shares are mounted from
+    #[instrument(skip(self), fields(host = %self.host))]
+    pub fn is_up(&mut self) -> bool {
+        let started = Instant::now();
+        let is_up = self.probe_endpoints();
+        metrics::observe_probe(&self.host, started.elapsed());
+        is_up
+    }
+
+    /// Probe the server's hosts, switching to the highest priority one that answers
+    ///
+    /// While the server is up only the host it is reached through is probed,
+    /// along with the higher priority hosts every so often so it can fail back
+    /// to them. The other hosts are only probed once that host stops answering
+    fn probe_endpoints(&mut self) -> bool {
+        let all = (0..self.endpoints.len()).collect::<Vec<_>>();
+        if !self.was_up {
+            return self.probe_concurrently(&all);
+        }
+
+        // Check the current host, and from time to time the ones before it
+        let first = if self.active > 0 && Instant::now() >= self.fail_back_at {
+            self.fail_back_at = Instant::now() + FAIL_BACK_INTERVAL;
+            (0..=self.active).collect()
+        } else {
+            vec![self.active]
+        };
+        if self.probe_concurrently(&first) {
+            return true;
+        }
+
+        // The current host stopped answering, so fail over to any of the others
+        let rest = all
+            .into_iter()
+            .filter(|index| !first.contains(index))
+            .collect::<Vec<_>>();
+        self.probe_concurrently(&rest)
+    }
+
+    /// Probe the given hosts at the same time, switching to the highest priority one that answers
+    fn probe_concurrently(&mut self, indices: &[usize]) -> bool {
+        // Probe each host on its own thread so unreachable hosts do not add up their timeouts
+        let (port, probe, timeout) = (self.port, self.probe, self.probe_timeout);
+        let active = self.was_up.then_some(self.active);
+        let span = Span::current();
+        let results = thread::scope(|scope| {
+            let probes = self
+                .endpoints
+                .iter_mut()
+                .enumerate()
+                .filter(|(index, _)| indices.contains(index))
+                .map(|(index, endpoint)| {
+                    let is_active = active == Some(index);
+                    let span = span.clone();
+                    let probe = scope.spawn(move || {
+                        span.in_scope(|| probe_endpoint(endpoint, port, probe, timeout, is_active))
+                    });
+                    (index, probe)
+                })
+                .collect::<Vec<_>>();
+            probes
+                .into_iter()
+                .map(|(index, probe)| (index, probe.join().ok().flatten()))
+                .collect::<Vec<_>>()
+        });
+
+        // Take the highest priority host that answered
+        let Some((index, (address, negotiated))) = results
+            .into_iter()
+            .find_map(|(index, result)| Some((index, result?)))
+        else {
+            return false;
+        };
+        if self.address != Some(address) {
+            debug!("Connected to {} at {}", self.endpoints[index].host, address);
+            self.address = Some(address);
+        }
+        if let Some(negotiated) = negotiated {
+            self.record_negotiated(negotiated);
+        }
+
+        // Log whenever the server is reached through a different host
+        if index != self.active {
+            info!(
+                "{} is now reached through {} instead of {}",
+                self.host, self.endpoints[index].host, self.endpoints[self.active].host
+            );
+            self.active = index;
+            self.fail_back_at = Instant::now() + FAIL_BACK_INTERVAL;
+        }
+        true
+    }
+
+    /// Every host the server is known by, starting with the one it was last reached through
+    fn mount_hosts(&self) -> Vec<String> {
+        let active = &self.endpoints[self.active];
+        std::iter::once(&active.host)
+            .chain(
+                self.endpoints
+                    .iter()
+                    .filter(|endpoint| endpoint.host != active.host)
+                    .map(|endpoint| &endpoint.host),
+            )
+            .cloned()
+            .collect()
+    }
+
+    /// Log the server GUID the first time it is seen, and warn whenever it changes
+    fn record_negotiated(&mut self, negotiated: Negotiated) {
+        match self.server_guid {
+            Some(guid) if guid == negotiated.guid => return,
+            Some(guid) => warn!(
+                "Server GUID for {} changed from {} to {}, the host name may now point at a different server",
+                self.host, guid, negotiated.guid
+            ),
+            None => info!(
+                "{} is SMB server {} (dialect {})",
+                self.host,
+                negotiated.guid,
+                smb::dialect_name(negotiated.dialect)
+            ),
+        }
+        self.server_guid = Some(negotiated.guid);
+    }
+
+    /// Handle the result of a reachability check, remounting shares while the server is up
+    #[instrument(skip(self), fields(host = %self.host))]
+    pub fn update(&mut self, is_up: bool) -> Result<()> {
+        // Schedule the next probe
+        self.next_poll = Instant::now() + self.poll_interval;
+
+        // Check if the socket is up or down and handle state changes
+        if !is_up {
+            if self.was_up {
+                // Log that the connection is down
+                info!(
+                    "{}:{} is down, will attempt to remount when it is back up",
+                    self.host, self.port
+                );
+
+                // Update state to indicate the connection is now down
+                self.was_up = false;
+
+                // Nothing is known about the shares until the server is back
+                self.smb_shares.iter_mut().for_each(Share::reset);
+            }
+            return Ok(());
+        }
+
+        if !self.was_up {
+            // Update state to indicate the connection is now up
+            self.was_up = true;
+            self.needs_post_mount = true;
+
+            // Log that the connection is back up
+            info!(
+                "{}:{} is up, attempting to remount...",
+                self.host, self.port
+            );
+        }
+
+        // Check each share, remounting any that need it
+        self.remount_shares();
+        self.run_post_mount_script()
+    }
+
+    /// Run the post-mount script if one is pending and every share is mounted
+    ///
+    /// Shares unmounted on request do not hold the script back
+    fn run_post_mount_script(&mut self) -> Result<()> {
+        if self.needs_post_mount && self.smb_shares.iter().all(Share::is_settled) {
+            self.needs_post_mount = false;
+            info!("Remount successful");
+
+            // If a post-mount script is provided, execute it
+            if let Some(script) = &self.post_mount_script {
+                info!("Executing post-mount script: {}", script);
+                let status = process::status(Command::new("sh").arg("-c").arg(script))?;
+                metrics::record_post_mount_script(&self.host, status.code());
+                if !status.success() {
+                    error!("Post-mount script failed with status: {}", status);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record the result of a reachability check and work out the state of each share
+    ///
+    /// Nothing is mounted or recovered
+    #[instrument(skip(self), fields(host = %self.host))]
+    pub fn inspect(&mut self, is_up: bool) {
+        self.was_up = is_up;
+        let hosts = self.mount_hosts();
+        for share in &mut self.smb_shares {
+            share.inspect(&self.backend, &hosts);
+        }
+    }
+
+    /// Check a share straight away on request, remounting it if needed
+    ///
+    /// Returns a description of the share's state afterwards, or an error if
+    /// it could not be mounted
+    #[instrument(skip(self), fields(host = %self.host))]
+    pub fn remount_share(&mut self, index: usize) -> Result<String> {
+        if !self.was_up {
+            return Err(anyhow::anyhow!("{} is down", self.host));
+        }
+
+        // Check the share, running the post-mount script if it was mounted
+        let hosts = self.mount_hosts();
+        let share = &mut self.smb_shares[index];
+        if share.remount(&self.backend, &hosts, self.port) {
+            self.needs_post_mount = true;
+        }
+        self.run_post_mount_script()?;
+
+        // Report the outcome
+        let share = &self.smb_shares[index];
+        let description = format!(
+            "{} at {} is {}",
+            share.name,
+            share.mount_point.display(),
+            share.state
+        );
+        match share.state {
+            state if state.is_mounted() => Ok(description),
+            ShareState::Failed(_) => Err(anyhow::anyhow!(
+                "Could not mount {} at {}: {}",
+                share.name,
+                share.mount_point.display(),
+                share.last_error().unwrap_or("unknown error")
+            )),
+            _ => Err(anyhow::anyhow!(description)),
+        }
+    }
+
+    /// Unmount a share on request, leaving it unmounted until it is remounted
+    #[instrument(skip(self), fields(host = %self.host))]
+    pub fn unmount_share(&mut self, index: usize) -> Result<String> {
+        let hosts = self.mount_hosts();
+        let share = &mut self.smb_shares[index];
+        share.hold(&self.backend, &hosts)?;
+        Ok(format!(
+            "{} at {} is {}",
+            share.name,
+            share.mount_point.display(),
+            share.state
+        ))
+    }
+
+    /// Unmount every share this daemon mounted itself
+    #[instrument(skip(self), fields(host = %self.host))]
+    pub fn unmount_shares(&mut self) {
+        let hosts = self.mount_hosts();
+        for share in &mut self.smb_shares {
+            share.unmount_if_mounted_here(&self.backend, &hosts);
+        }
+    }
+
+    /// Check every share, remounting any that are not mounted
+    #[instrument(skip(self))]
+    fn remount_shares(&mut self) {
+        let hosts = self.mount_hosts();
+        for share in &mut self.smb_shares {
+            // Run the post-mount script again after any share is mounted
+            if share.check(&self.backend, &hosts, self.port) {
+                self.needs_post_mount = true;
+            }
+        }
+    }
+}
+
+/// Check if a server is reachable through one of its hosts
+///
+/// Returns the address that answered, and with the SMB probe the NEGOTIATE response
+fn probe_endpoint(
+    endpoint: &mut Endpoint,
+    port: u16,
+    probe: ProbeKind,
+    timeout: Duration,
+    is_active: bool,
+) -> Option<(SocketAddr, Option<Negotiated>)> {
+    // Resolve the host name again periodically, and on every probe while it is down
+    if !is_active || endpoint.resolved_at.elapsed() >= RESOLVE_INTERVAL {
+        endpoint.resolve(port);
+    }
+
+    // Attempt to connect to any of the addresses within the probe timeout
+    let (mut stream, address) = match net::connect(&endpoint.addresses, timeout) {
+        Ok(connected) => connected,
+        Err(e) => {
+            debug!("Could not connect to {}:{}: {:#}", endpoint.host, port, e);
+            return None;
+        }
+    };
+    if probe == ProbeKind::Tcp {
+        return Some((address, None));
+    }
+
+    // Make sure an SMB server is actually answering on the other end
+    match smb::negotiate(&mut stream, timeout) {
+        Ok(negotiated) => Some((address, Some(negotiated))),
+        Err(e) => {
+            if is_active {
+                warn!(
+                    "{}:{} accepted the connection but SMB negotiation failed: {:#}",
+                    endpoint.host, port, e
+                );
+            } else {
+                debug!(
+                    "SMB negotiation with {}:{} failed: {:#}",
+                    endpoint.host, port, e
+                );
+            }
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, net::TcpListener, thread};
+
+    use super::*;
+    use crate::backend::stub::StubBackend;
+
+    #[test]
+    fn remounts_after_reconnecting() {
+        // A server whose only share is refused once, with a script that records each run
+        let runs =
+            std::env::temp_dir().join(format!("remounter-{}-post-mount", std::process::id()));
+        let _ = fs::remove_file(&runs);
+        let config: Config = toml::from_str(&format!(
+            r#"
+            [retry]
+            initial_delay = "50ms"
+            jitter = 0.0
+
+            [[server]]
+            host = "127.0.0.1"
+            post_mount_script = "echo ran >> '{}'"
+            share = [{{ name = "Media" }}]
+            "#,
+            runs.display()
+        ))
+        .unwrap();
+        let backend: Arc<dyn MountBackend> = Arc::new(StubBackend::new(1));
+        let mut server = new_server_with_backend(&config.servers[0], &config, backend).unwrap();
+        let state = |server: &Server| server.shares()[0].state;
+        let run_count = || fs::read_to_string(&runs).map_or(0, |runs| runs.lines().count());
+
+        // Nothing is mounted while the server is down
+        server.update(false).unwrap();
+        assert!(!server.is_reachable());
+        assert_eq!(state(&server), ShareState::Unknown);
+
+        // Once it is up the first mount fails and backs off, holding back the script
+        server.update(true).unwrap();
+        assert!(server.is_reachable());
+        assert!(matches!(state(&server), ShareState::Failed(_)));
+        server.update(true).unwrap();
+        assert_eq!(server.shares()[0].mount_attempts(), 1);
+        assert_eq!(run_count(), 0);
+
+        // The retry mounts the share and runs the script once
+        thread::sleep(Duration::from_millis(60));
+        server.update(true).unwrap();
+        assert_eq!(state(&server), ShareState::Mounted);
+        server.update(true).unwrap();
+        assert_eq!(run_count(), 1);
+
+        // Going down forgets the shares, coming back checks them and runs the script again
+        server.update(false).unwrap();
+        assert_eq!(state(&server), ShareState::Unknown);
+        server.update(true).unwrap();
+        assert_eq!(state(&server), ShareState::Mounted);
+        assert_eq!(server.shares()[0].mount_attempts(), 2);
+        assert_eq!(run_count(), 2);
+
+        fs::remove_file(runs).unwrap();
+    }
+
+    #[test]
+    fn fails_over_and_back_between_hosts() {
+        // Only the fallback host is listening to begin with
+        let fallback = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = fallback.local_addr().unwrap().port();
+        let config: Config = toml::from_str(&format!(
+            r#"
+            probe = "tcp"
+            probe_timeout = "1s"
+
+            [[server]]
+            host = "127.0.0.2"
+            fallback_hosts = ["127.0.0.1"]
+            port = {}
+            share = [{{ name = "Media" }}]
+            "#,
+            port
+        ))
+        .unwrap();
+        let backend: Arc<dyn MountBackend> = Arc::new(StubBackend::new(0));
+        let mut server = new_server_with_backend(&config.servers[0], &config, backend).unwrap();
+        let reached_through = |server: &Server| server.status().reached_through;
+
+        assert!(server.is_up());
+        assert_eq!(reached_through(&server), "127.0.0.1");
+        server.update(true).unwrap();
+
+        // The primary host coming back is only noticed once it is time to fail back
+        let primary = TcpListener::bind(("127.0.0.2", port)).unwrap();
+        assert!(server.is_up());
+        assert_eq!(reached_through(&server), "127.0.0.1");
+        server.fail_back_at = Instant::now();
+        assert!(server.is_up());
+        assert_eq!(reached_through(&server), "127.0.0.2");
+
+        // Losing the current host fails over straight away
+        drop(primary);
+        assert!(server.is_up());
+        assert_eq!(reached_through(&server), "127.0.0.1");
+    }
+}
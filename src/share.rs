@@ -0,0 +1,756 @@
+use std::{
+    env, fmt, mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::{
+    backend::{DegradedReason, MountBackend, ShareHealth},
+    config::ShareConfig,
+    mount_table::{self, Mismatch},
+    probe::{HealthCheck, Prober},
+    process,
+    retry::RetryPolicy,
+    status::ShareStatus,
+};
+
+/// Retry bookkeeping for a share that failed to mount
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// The number of consecutive failed mount attempts
+    pub attempts: u32,
+    /// When the next mount attempt is due, or None if the share has given up
+    pub retry_at: Option<Instant>,
+}
+
+/// The state of a single monitored share
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareState {
+    /// The share has not been checked since the server came up
+    Unknown,
+    /// The share is mounted and healthy
+    Mounted,
+    /// Something is mounted at the mount point but it is not healthy
+    Stale,
+    /// Something other than this share is mounted at the mount point
+    Mismatched,
+    /// The share is mounted and readable but cannot be written to
+    Degraded(DegradedReason),
+    /// Nothing is mounted at the mount point
+    Unmounted,
+    /// A mount attempt is in progress
+    Mounting,
+    /// The last mount attempt failed and will be retried
+    Failed(Backoff),
+    /// The share was unmounted on request and is left alone until remounted
+    Held,
+}
+
+impl ShareState {
+    /// The short name of every state, as used in metrics
+    pub const LABELS: [&str; 10] = [
+        "unknown",
+        "mounted",
+        "stale",
+        "mismatched",
+        "degraded",
+        "unmounted",
+        "mounting",
+        "failed",
+        "gave_up",
+        "held",
+    ];
+
+    /// The short name of the state, as used in metrics
+    pub fn label(&self) -> &'static str {
+        match self {
+            ShareState::Unknown => "unknown",
+            ShareState::Mounted => "mounted",
+            ShareState::Stale => "stale",
+            ShareState::Mismatched => "mismatched",
+            ShareState::Degraded(_) => "degraded",
+            ShareState::Unmounted => "unmounted",
+            ShareState::Mounting => "mounting",
+            ShareState::Failed(Backoff { retry_at: None, .. }) => "gave_up",
+            ShareState::Failed(_) => "failed",
+            ShareState::Held => "held",
+        }
+    }
+
+    /// Check whether the share is mounted and usable, even if degraded
+    pub fn is_mounted(&self) -> bool {
+        matches!(self, ShareState::Mounted | ShareState::Degraded(_))
+    }
+}
+
+impl fmt::Display for ShareState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShareState::Unknown => write!(f, "unknown"),
+            ShareState::Mounted => write!(f, "mounted"),
+            ShareState::Stale => write!(f, "stale"),
+            ShareState::Mismatched => write!(f, "mismatched"),
+            ShareState::Degraded(DegradedReason::ReadOnly) => write!(f, "degraded (read-only)"),
+            ShareState::Degraded(DegradedReason::WriteFailed) => {
+                write!(f, "degraded (write failed)")
+            }
+            ShareState::Unmounted => write!(f, "unmounted"),
+            ShareState::Mounting => write!(f, "mounting"),
+            ShareState::Failed(Backoff { retry_at: None, .. }) => write!(f, "gave up"),
+            ShareState::Failed(_) => write!(f, "failed"),
+            ShareState::Held => write!(f, "unmounted (on request)"),
+        }
+    }
+}
+
+/// An SMB share and the local path it is mounted at
+#[derive(Debug)]
+pub struct Share {
+    /// The name of the share on the server
+    pub name: String,
+    /// The local directory the share is mounted at
+    pub mount_point: PathBuf,
+    /// How failed mounts are retried
+    pub retry: RetryPolicy,
+    /// How the health of the share is checked
+    pub health: HealthCheck,
+    /// The current state of the share
+    pub state: ShareState,
+    /// Runs health probes without blocking on a hung mount
+    prober: Prober,
+    /// Whether the share is currently mounted because this daemon mounted it
+    mounted_here: bool,
+    /// When the daemon last mounted the share
+    last_mounted: Option<OffsetDateTime>,
+    /// The last error mounting the share
+    last_error: Option<String>,
+    /// The number of mount attempts since the daemon started
+    mount_attempts: u64,
+    /// The number of failed mount attempts since the daemon started
+    mount_failures: u64,
+}
+
+impl Share {
+    /// Create a share from its configuration
+    ///
+    /// Shares without an explicit mount point are mounted under `mount_root`
+    pub fn from_config(
+        config: &ShareConfig,
+        mount_root: &Path,
+        retry: RetryPolicy,
+        health: HealthCheck,
+    ) -> Self {
+        // Strip whitespace and any leading slash from the share name
+        let name = config.name.trim().trim_start_matches('/');
+
+        // Use the explicit mount point if given, otherwise mount under the root
+        let mount_point = match &config.mount_point {
+            Some(mount_point) => expand_home(mount_point),
+            None => mount_root.join(name),
+        };
+
+        Self {
+            name: name.to_string(),
+            mount_point,
+            retry,
+            health,
+            state: ShareState::Unknown,
+            prober: Prober::default(),
+            mounted_here: false,
+            last_mounted: None,
+            last_error: None,
+            mount_attempts: 0,
+            mount_failures: 0,
+        }
+    }
+
+    /// Check whether two shares describe the same share at the same mount point
+    pub fn is_same(&self, other: &Share) -> bool {
+        self.name == other.name && self.mount_point == other.mount_point
+    }
+
+    /// Take over the state of the share this one replaces after a configuration reload
+    pub fn take_state(&mut self, old: &mut Share) {
+        self.state = old.state;
+        self.prober = mem::take(&mut old.prober);
+        self.mounted_here = old.mounted_here;
+        self.last_mounted = old.last_mounted;
+        self.last_error = old.last_error.take();
+        self.mount_attempts = old.mount_attempts;
+        self.mount_failures = old.mount_failures;
+    }
+
+    /// A snapshot of the share's state for status reports
+    pub fn status(&self) -> ShareStatus {
+        let next_retry = match self.state {
+            ShareState::Failed(Backoff {
+                retry_at: Some(retry_at),
+                ..
+            }) => Some(format_deadline(
+                retry_at.saturating_duration_since(Instant::now()),
+            )),
+            _ => None,
+        };
+        ShareStatus {
+            name: self.name.clone(),
+            mount_point: self.mount_point.clone(),
+            state: self.state.to_string(),
+            failed_attempts: self.attempts(),
+            last_mounted: self.last_mounted.map(format_time),
+            last_error: self.last_error.clone(),
+            next_retry,
+        }
+    }
+
+    /// Forget what is known about the share, e.g. because the server went down
+    ///
+    /// Shares unmounted on request stay unmounted
+    pub fn reset(&mut self) {
+        if self.state != ShareState::Held {
+            self.state = ShareState::Unknown;
+        }
+    }
+
+    /// Check whether the share is mounted, or deliberately left unmounted
+    pub fn is_settled(&self) -> bool {
+        self.state.is_mounted() || self.state == ShareState::Held
+    }
+
+    /// The last error mounting the share
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// The number of mount attempts since the daemon started
+    pub fn mount_attempts(&self) -> u64 {
+        self.mount_attempts
+    }
+
+    /// The number of failed mount attempts since the daemon started
+    pub fn mount_failures(&self) -> u64 {
+        self.mount_failures
+    }
+
+    /// Check the share and mount it if needed, returning true if it was mounted
+    ///
+    /// `hosts` lists every name the server may be mounted from, the first one
+    /// is used for new mounts. Failed shares are only retried once their backoff
+    /// has expired
+    #[instrument(skip(self, backend), fields(share = %self.name))]
+    pub fn check(&mut self, backend: &Arc<dyn MountBackend>, hosts: &[String], port: u16) -> bool {
+        // Leave shares unmounted on request alone
+        if self.state == ShareState::Held {
+            return false;
+        }
+
+        // Wait for the backoff to expire before retrying a failed share
+        if let ShareState::Failed(backoff) = self.state
+            && backoff
+                .retry_at
+                .is_none_or(|retry_at| Instant::now() < retry_at)
+        {
+            return false;
+        }
+
+        // Make sure whatever is mounted at the mount point is really this share
+        if let Err(mismatch) = self.verify_mount(hosts) {
+            if self.state != ShareState::Mismatched {
+                error!(
+                    "Wrong filesystem mounted at {}: {}",
+                    self.mount_point.display(),
+                    mismatch
+                );
+            }
+            self.state = ShareState::Mismatched;
+            self.mounted_here = false;
+            return false;
+        }
+
+        // Work out the current state of the share, treating a hung probe as stale
+        let health = self.probe(backend);
+
+        // Leave the share alone while shutting down, the probe may have been cut short
+        if process::is_terminating() {
+            return false;
+        }
+
+        match health {
+            Some(ShareHealth::Healthy) => {
+                if self.state != ShareState::Mounted {
+                    info!(
+                        "Share {} is mounted and healthy",
+                        self.mount_point.display()
+                    );
+                }
+                self.state = ShareState::Mounted;
+                false
+            }
+            Some(ShareHealth::Unhealthy) => {
+                if !self.is_stale() {
+                    warn!(
+                        "Share {} exists but is not healthy",
+                        self.mount_point.display()
+                    );
+                }
+                self.handle_stale(backend, hosts, port)
+            }
+            None => {
+                if !self.is_stale() {
+                    warn!(
+                        "Health check of {} did not finish within {:.1}s",
+                        self.mount_point.display(),
+                        self.health.timeout.as_secs_f64()
+                    );
+                }
+                self.handle_stale(backend, hosts, port)
+            }
+            Some(ShareHealth::Degraded(reason)) => {
+                if self.state != ShareState::Degraded(reason) {
+                    warn!(
+                        "Share {} is mounted but degraded: {}",
+                        self.mount_point.display(),
+                        describe_degraded(reason)
+                    );
+                }
+                self.state = ShareState::Degraded(reason);
+                false
+            }
+            Some(ShareHealth::MarkerMismatch) => {
+                if self.state != ShareState::Mismatched {
+                    error!(
+                        "Wrong share mounted at {}: the marker {} does not have the expected content",
+                        self.mount_point.display(),
+                        self.health.marker.display()
+                    );
+                }
+                self.state = ShareState::Mismatched;
+                false
+            }
+            Some(ShareHealth::NotMounted) => {
+                self.mounted_here = false;
+
+                // Keep the backoff of a failed share so retries keep counting
+                if !matches!(self.state, ShareState::Failed(_)) {
+                    self.state = ShareState::Unmounted;
+                }
+
+                // Mount the share using the configured backend
+                self.mount(backend, hosts, port)
+            }
+        }
+    }
+
+    /// Work out the state of the share without mounting or recovering it
+    #[instrument(skip(self, backend), fields(share = %self.name))]
+    pub fn inspect(&mut self, backend: &Arc<dyn MountBackend>, hosts: &[String]) {
+        if let Err(mismatch) = self.verify_mount(hosts) {
+            warn!(
+                "Wrong filesystem mounted at {}: {}",
+                self.mount_point.display(),
+                mismatch
+            );
+            self.state = ShareState::Mismatched;
+            return;
+        }
+        self.state = match self.probe(backend) {
+            Some(ShareHealth::Healthy) => ShareState::Mounted,
+            Some(ShareHealth::Degraded(reason)) => ShareState::Degraded(reason),
+            Some(ShareHealth::Unhealthy) | None => ShareState::Stale,
+            Some(ShareHealth::MarkerMismatch) => ShareState::Mismatched,
+            Some(ShareHealth::NotMounted) => ShareState::Unmounted,
+        };
+    }
+
+    /// Probe the health of the share with a timeout
+    fn probe(&mut self, backend: &Arc<dyn MountBackend>) -> Option<ShareHealth> {
+        self.prober.probe(backend, &self.mount_point, &self.health)
+    }
+
+    /// Check the mount table to make sure anything mounted at the mount point is this share
+    ///
+    /// Having nothing mounted is not a mismatch, and neither is being unable to
+    /// read the mount table, in which case the health probe decides
+    fn verify_mount(&self, hosts: &[String]) -> Result<(), Mismatch> {
+        match mount_table::find(&self.mount_point) {
+            Ok(Some(entry)) => entry.matches(hosts, &self.name),
+            Ok(None) => Ok(()),
+            Err(e) => {
+                warn!("Could not read the mount table: {}", e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Check whether the share has already been found to be stale or failing
+    fn is_stale(&self) -> bool {
+        matches!(self.state, ShareState::Stale | ShareState::Failed(_))
+    }
+
+    /// The number of consecutive failed mount attempts
+    fn attempts(&self) -> u32 {
+        match self.state {
+            ShareState::Failed(backoff) => backoff.attempts,
+            _ => 0,
+        }
+    }
+
+    /// Handle a share that is mounted but not healthy, recovering it if enabled
+    fn handle_stale(
+        &mut self,
+        backend: &Arc<dyn MountBackend>,
+        hosts: &[String],
+        port: u16,
+    ) -> bool {
+        // Without recovery the share is left alone
+        if !self.health.recover_stale {
+            if self.state != ShareState::Stale {
+                warn!(
+                    "Share {} is stale, not remounting",
+                    self.mount_point.display()
+                );
+            }
+            self.state = ShareState::Stale;
+            return false;
+        }
+        self.recover(backend, hosts, port)
+    }
+
+    /// Force a stale mount off and mount the share again
+    fn recover(&mut self, backend: &Arc<dyn MountBackend>, hosts: &[String], port: u16) -> bool {
+        // Never unmount anything that is not an SMB network mount
+        let not_recoverable = match mount_table::find(&self.mount_point) {
+            Ok(Some(entry)) => entry
+                .matches(hosts, &self.name)
+                .err()
+                .map(|mismatch| mismatch.to_string()),
+            Ok(None) => Some("nothing is mounted there".to_string()),
+            Err(e) => Some(format!("could not read the mount table: {}", e)),
+        };
+        if let Some(reason) = not_recoverable {
+            if self.state != ShareState::Stale {
+                warn!(
+                    "Not recovering stale share {}: {}",
+                    self.mount_point.display(),
+                    reason
+                );
+            }
+            self.state = ShareState::Stale;
+            return false;
+        }
+
+        // Force the stale mount off, then mount the share again
+        info!(
+            "Force-unmounting stale share {}",
+            self.mount_point.display()
+        );
+        if let Err(e) = backend.unmount(&self.mount_point, true) {
+            self.fail(e);
+            return false;
+        }
+        self.mount(backend, hosts, port)
+    }
+
+    /// Mount the share from the first host, recording the outcome in its state
+    fn mount(&mut self, backend: &Arc<dyn MountBackend>, hosts: &[String], port: u16) -> bool {
+        // Count previous failures so retries can be tracked
+        let attempts = self.attempts();
+        let host = &hosts[0];
+
+        info!(
+            "Mounting //{}/{} at {}",
+            host,
+            self.name,
+            self.mount_point.display()
+        );
+        self.state = ShareState::Mounting;
+        self.mount_attempts += 1;
+
+        // Mount the share and make sure it actually appeared at the mount point
+        let result = backend
+            .mount(host, port, &self.name, &self.mount_point)
+            .and_then(|()| {
+                self.verify_mount(hosts)
+                    .map_err(|mismatch| anyhow::anyhow!("wrong filesystem mounted: {}", mismatch))
+            })
+            .and_then(|()| match self.probe(backend) {
+                Some(ShareHealth::Healthy) => Ok(ShareState::Mounted),
+                Some(ShareHealth::Degraded(reason)) => Ok(ShareState::Degraded(reason)),
+                Some(ShareHealth::Unhealthy) => Err(anyhow::anyhow!(
+                    "mounted at {} but it is not healthy",
+                    self.mount_point.display()
+                )),
+                Some(ShareHealth::MarkerMismatch) => Err(anyhow::anyhow!(
+                    "wrong share mounted at {}, the marker {} does not have the expected content",
+                    self.mount_point.display(),
+                    self.health.marker.display()
+                )),
+                Some(ShareHealth::NotMounted) => Err(anyhow::anyhow!(
+                    "mount succeeded but nothing is mounted at {}",
+                    self.mount_point.display()
+                )),
+                None => Err(anyhow::anyhow!(
+                    "health check of {} timed out after mounting",
+                    self.mount_point.display()
+                )),
+            });
+
+        match result {
+            Ok(ShareState::Degraded(reason)) => {
+                warn!(
+                    "Mounted {} at {} but it is degraded: {}",
+                    self.name,
+                    self.mount_point.display(),
+                    describe_degraded(reason)
+                );
+                self.state = ShareState::Degraded(reason);
+                self.mounted_here = true;
+                self.last_mounted = Some(OffsetDateTime::now_utc());
+                true
+            }
+            Ok(state) => {
+                info!("Mounted {} at {}", self.name, self.mount_point.display());
+                self.state = state;
+                self.mounted_here = true;
+                self.last_mounted = Some(OffsetDateTime::now_utc());
+                true
+            }
+            Err(e) if process::is_terminating() => {
+                info!("Mounting {} interrupted by shutdown: {:#}", self.name, e);
+                self.mount_failures += 1;
+                self.state = ShareState::Unmounted;
+                false
+            }
+            Err(e) => {
+                self.mount_failures += 1;
+
+                // Restore the failure count that was replaced by the mounting state
+                self.state = ShareState::Failed(Backoff {
+                    attempts,
+                    retry_at: None,
+                });
+                self.fail(e);
+                false
+            }
+        }
+    }
+
+    /// Check the share straight away, forgetting any backoff or earlier unmount request
+    ///
+    /// A stale share is recovered even if automatic recovery is disabled.
+    /// Returns true if the share was mounted
+    #[instrument(skip(self, backend), fields(share = %self.name))]
+    pub fn remount(
+        &mut self,
+        backend: &Arc<dyn MountBackend>,
+        hosts: &[String],
+        port: u16,
+    ) -> bool {
+        info!("Checking {} on request", self.mount_point.display());
+        self.state = ShareState::Unknown;
+        let mounted = self.check(backend, hosts, port);
+
+        // The check leaves stale shares alone unless recovery is enabled
+        if self.state == ShareState::Stale && !self.health.recover_stale {
+            return self.recover(backend, hosts, port);
+        }
+        mounted
+    }
+
+    /// Unmount the share and leave it unmounted until it is remounted on request
+    #[instrument(skip(self, backend), fields(share = %self.name))]
+    pub fn hold(&mut self, backend: &Arc<dyn MountBackend>, hosts: &[String]) -> Result<()> {
+        self.unmount(backend, hosts)?;
+        info!(
+            "Leaving {} unmounted until it is remounted on request",
+            self.mount_point.display()
+        );
+        self.state = ShareState::Held;
+        Ok(())
+    }
+
+    /// Unmount the share if this daemon mounted it, e.g. when shutting down
+    ///
+    /// Shares that were already mounted when they were first checked are left alone
+    #[instrument(skip(self, backend), fields(share = %self.name))]
+    pub fn unmount_if_mounted_here(&mut self, backend: &Arc<dyn MountBackend>, hosts: &[String]) {
+        if !self.mounted_here {
+            return;
+        }
+        if let Err(e) = self.unmount(backend, hosts) {
+            error!("Error unmounting {}: {:#}", self.mount_point.display(), e);
+        }
+    }
+
+    /// Unmount the share if it is what is mounted at the mount point
+    fn unmount(&mut self, backend: &Arc<dyn MountBackend>, hosts: &[String]) -> Result<()> {
+        // Only unmount the share if it is still what is mounted at the mount point
+        match mount_table::find(&self.mount_point) {
+            Ok(Some(entry)) => entry.matches(hosts, &self.name).map_err(|mismatch| {
+                anyhow::anyhow!("not unmounting a different filesystem: {}", mismatch)
+            })?,
+            Ok(None) => {
+                debug!(
+                    "{} is not mounted at {}",
+                    self.name,
+                    self.mount_point.display()
+                );
+                self.mounted_here = false;
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "not unmounting, could not read the mount table: {}",
+                    e
+                ));
+            }
+        }
+
+        // Unmount cleanly, forcing it if the share is busy or the server is gone
+        info!(
+            "Unmounting {} from {}",
+            self.name,
+            self.mount_point.display()
+        );
+        backend.unmount(&self.mount_point, false).or_else(|e| {
+            warn!(
+                "Could not unmount {}, forcing it: {:#}",
+                self.mount_point.display(),
+                e
+            );
+            backend.unmount(&self.mount_point, true)
+        })?;
+        self.mounted_here = false;
+        self.state = ShareState::Unmounted;
+        Ok(())
+    }
+
+    /// Record a failed attempt and schedule the next one according to the retry policy
+    fn fail(&mut self, error: anyhow::Error) {
+        // Work out when to try again, if at all
+        let attempts = self.attempts() + 1;
+        let delay = self.retry.next_delay(attempts);
+        match delay {
+            Some(delay) => error!(
+                "Error mounting {} (attempt {}): {}, retrying in {:.1}s at {}",
+                self.name,
+                attempts,
+                error,
+                delay.as_secs_f64(),
+                format_deadline(delay)
+            ),
+            None => error!(
+                "Error mounting {} (attempt {}): {}, giving up until the server reconnects",
+                self.name, attempts, error
+            ),
+        }
+        self.state = ShareState::Failed(Backoff {
+            attempts,
+            retry_at: delay.map(|delay| Instant::now() + delay),
+        });
+        self.last_error = Some(format!("{:#}", error));
+    }
+}
+
+/// Describe why a share is degraded
+fn describe_degraded(reason: DegradedReason) -> &'static str {
+    match reason {
+        DegradedReason::ReadOnly => "the share is read-only",
+        DegradedReason::WriteFailed => "writing to the share failed",
+    }
+}
+
+/// The wall-clock time a delay from now will expire, if it can be represented
+pub fn deadline(delay: Duration) -> Option<OffsetDateTime> {
+    OffsetDateTime::now_utc().checked_add(delay.try_into().ok()?)
+}
+
+/// Format the wall-clock time a delay from now will expire, or "never" if it is too far away
+pub fn format_deadline(delay: Duration) -> String {
+    deadline(delay).map_or_else(|| "never".to_string(), format_time)
+}
+
+/// Format a wall-clock time as RFC3339
+fn format_time(time: OffsetDateTime) -> String {
+    time.format(&Rfc3339)
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Expand a leading `~` in a path to the user's home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), env::var_os("HOME")) {
+        (Some(""), Some(home)) => PathBuf::from(home),
+        (Some(rest), Some(home)) if rest.starts_with('/') => {
+            PathBuf::from(home).join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{backend::stub::StubBackend, config::DEFAULT_PORT};
+
+    #[test]
+    fn retries_failed_mounts_after_the_backoff() {
+        let backend: Arc<dyn MountBackend> = Arc::new(StubBackend::new(1));
+        let config = ShareConfig {
+            name: "share".to_string(),
+            mount_point: None,
+            retry: Default::default(),
+            health: Default::default(),
+        };
+        let retry = RetryPolicy {
+            initial_delay: Duration::from_millis(50),
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+        let mut share =
+            Share::from_config(&config, backend.mount_root(), retry, HealthCheck::default());
+        let hosts = ["nas".to_string()];
+
+        // Nothing is mounted to begin with
+        share.inspect(&backend, &hosts);
+        assert_eq!(share.state, ShareState::Unmounted);
+
+        // The first attempt fails and backs off
+        assert!(!share.check(&backend, &hosts, DEFAULT_PORT));
+        assert!(matches!(
+            share.state,
+            ShareState::Failed(Backoff {
+                attempts: 1,
+                retry_at: Some(_)
+            })
+        ));
+        assert_eq!(share.last_error(), Some("server refused the mount"));
+
+        // Nothing is retried until the backoff expires
+        assert!(!share.check(&backend, &hosts, DEFAULT_PORT));
+        assert_eq!(share.mount_attempts(), 1);
+        thread::sleep(Duration::from_millis(60));
+
+        // The retry mounts the share, which is then left alone
+        assert!(share.check(&backend, &hosts, DEFAULT_PORT));
+        assert_eq!(share.state, ShareState::Mounted);
+        assert!(!share.check(&backend, &hosts, DEFAULT_PORT));
+        assert_eq!(share.state, ShareState::Mounted);
+        assert_eq!((share.mount_attempts(), share.mount_failures()), (2, 1));
+    }
+
+    #[test]
+    fn formats_unrepresentable_deadlines_as_never() {
+        assert_eq!(
+            format_deadline(Duration::from_secs(10_000 * 365 * 24 * 60 * 60)),
+            "never"
+        );
+        assert_eq!(format_deadline(Duration::MAX), "never");
+        assert_ne!(format_deadline(Duration::from_secs(60)), "never");
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{process, random};
+
+/// The SMB2 dialects offered in the NEGOTIATE request, oldest first
+const DIALECTS: [u16; 5] = [0x0202, 0x0210, 0x0300, 0x0302, 0x0311];
+
+/// The SMB 3.1.1 dialect, which requires a preauth integrity negotiate context
+const DIALECT_311: u16 = 0x0311;
+
+/// The size of the SMB2 header
+const HEADER_SIZE: usize = 64;
+
+/// How long each read waits before checking whether the daemon is shutting down
+const READ_SLICE: Duration = Duration::from_millis(100);
+
+/// The largest NEGOTIATE response we are willing to read
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// A server GUID as reported in the NEGOTIATE response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerGuid([u8; 16]);
+
+impl fmt::Display for ServerGuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The first three fields are little-endian, the rest are plain bytes
+        let b = &self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9],
+            b[10],
+            b[11],
+            b[12],
+            b[13],
+            b[14],
+            b[15]
+        )
+    }
+}
+
+/// The interesting parts of a successful NEGOTIATE response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    /// The dialect the server picked (e.g., 0x0311)
+    pub dialect: u16,
+    /// The server's GUID, which identifies the machine behind the host name
+    pub guid: ServerGuid,
+}
+
+/// Format an SMB2 dialect as a version number (e.g., 3.1.1)
+pub fn dialect_name(dialect: u16) -> &'static str {
+    match dialect {
+        0x0202 => "2.0.2",
+        0x0210 => "2.1",
+        0x0300 => "3.0",
+        0x0302 => "3.0.2",
+        0x0311 => "3.1.1",
+        _ => "unknown",
+    }
+}
+
+/// Send an SMB2 NEGOTIATE request over a connected stream and validate the response
+pub fn negotiate(stream: &mut TcpStream, timeout: Duration) -> Result<Negotiated> {
+    // Never block longer than the probe timeout
+    let deadline = Instant::now() + timeout;
+    stream.set_write_timeout(Some(timeout))?;
+
+    // Send the request in a single NetBIOS session message
+    stream
+        .write_all(&negotiate_request())
+        .context("Could not send SMB2 NEGOTIATE request")?;
+
+    // Read the NetBIOS session header, then the message it describes
+    let mut header = [0u8; 4];
+    read_full(stream, &mut header, deadline).context("Could not read SMB2 NEGOTIATE response")?;
+    if header[0] != 0 {
+        return Err(anyhow::anyhow!(
+            "Unexpected NetBIOS message type {:#04x}",
+            header[0]
+        ));
+    }
+    let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+    if length > MAX_RESPONSE_SIZE {
+        return Err(anyhow::anyhow!(
+            "NEGOTIATE response too large ({} bytes)",
+            length
+        ));
+    }
+    let mut message = vec![0u8; length];
+    read_full(stream, &mut message, deadline).context("Could not read SMB2 NEGOTIATE response")?;
+
+    parse_negotiate_response(&message)
+}
+
+/// Fill the buffer from the stream, giving up at the deadline or when the daemon is shutting down
+fn read_full(stream: &mut TcpStream, buffer: &mut [u8], deadline: Instant) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || process::is_terminating() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        stream.set_read_timeout(Some(remaining.min(READ_SLICE)))?;
+        match stream.read(&mut buffer[filled..]) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => filled += read,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Build a NEGOTIATE request offering SMB 2.0.2 to 3.1.1, including the NetBIOS header
+fn negotiate_request() -> Vec<u8> {
+    let mut message = Vec::with_capacity(192);
+
+    // SMB2 header
+    message.extend_from_slice(b"\xfeSMB");
+    message.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes()); // StructureSize
+    message.extend_from_slice(&0u16.to_le_bytes()); // CreditCharge
+    message.extend_from_slice(&0u32.to_le_bytes()); // Status
+    message.extend_from_slice(&0u16.to_le_bytes()); // Command (NEGOTIATE)
+    message.extend_from_slice(&1u16.to_le_bytes()); // CreditRequest
+    message.extend_from_slice(&0u32.to_le_bytes()); // Flags
+    message.extend_from_slice(&0u32.to_le_bytes()); // NextCommand
+    message.extend_from_slice(&0u64.to_le_bytes()); // MessageId
+    message.extend_from_slice(&0u32.to_le_bytes()); // Reserved
+    message.extend_from_slice(&0u32.to_le_bytes()); // TreeId
+    message.extend_from_slice(&0u64.to_le_bytes()); // SessionId
+    message.extend_from_slice(&[0u8; 16]); // Signature
+
+    // The negotiate context list follows the dialects, aligned to 8 bytes
+    let contexts_offset = (HEADER_SIZE + 36 + DIALECTS.len() * 2).next_multiple_of(8);
+
+    // NEGOTIATE request
+    message.extend_from_slice(&36u16.to_le_bytes()); // StructureSize
+    message.extend_from_slice(&(DIALECTS.len() as u16).to_le_bytes()); // DialectCount
+    message.extend_from_slice(&1u16.to_le_bytes()); // SecurityMode (signing enabled)
+    message.extend_from_slice(&0u16.to_le_bytes()); // Reserved
+    message.extend_from_slice(&0u32.to_le_bytes()); // Capabilities
+    message.extend_from_slice(&random::bytes::<16>()); // ClientGuid
+    message.extend_from_slice(&(contexts_offset as u32).to_le_bytes()); // NegotiateContextOffset
+    message.extend_from_slice(&1u16.to_le_bytes()); // NegotiateContextCount
+    message.extend_from_slice(&0u16.to_le_bytes()); // Reserved2
+    for dialect in DIALECTS {
+        message.extend_from_slice(&dialect.to_le_bytes());
+    }
+    message.resize(contexts_offset, 0);
+
+    // Preauth integrity context, required when offering SMB 3.1.1
+    message.extend_from_slice(&1u16.to_le_bytes()); // ContextType (PREAUTH_INTEGRITY_CAPABILITIES)
+    message.extend_from_slice(&38u16.to_le_bytes()); // DataLength
+    message.extend_from_slice(&0u32.to_le_bytes()); // Reserved
+    message.extend_from_slice(&1u16.to_le_bytes()); // HashAlgorithmCount
+    message.extend_from_slice(&32u16.to_le_bytes()); // SaltLength
+    message.extend_from_slice(&1u16.to_le_bytes()); // HashAlgorithms (SHA-512)
+    message.extend_from_slice(&random::bytes::<32>()); // Salt
+
+    // Prefix the NetBIOS session header
+    let length = (message.len() as u32).to_be_bytes();
+    let mut request = vec![0, length[1], length[2], length[3]];
+    request.extend_from_slice(&message);
+    request
+}
+
+/// Validate a NEGOTIATE response and extract the dialect and server GUID
+fn parse_negotiate_response(message: &[u8]) -> Result<Negotiated> {
+    // The fixed part of the response is 64 bytes of body after the header
+    if message.len() < HEADER_SIZE + 64 {
+        return Err(anyhow::anyhow!(
+            "NEGOTIATE response too short ({} bytes)",
+            message.len()
+        ));
+    }
+
+    // Check the SMB2 header
+    if &message[..4] != b"\xfeSMB" {
+        return Err(anyhow::anyhow!("Response is not an SMB2 message"));
+    }
+    let status = u32::from_le_bytes([message[8], message[9], message[10], message[11]]);
+    if status != 0 {
+        return Err(anyhow::anyhow!(
+            "NEGOTIATE failed with status {:#010x}",
+            status
+        ));
+    }
+    let command = u16::from_le_bytes([message[12], message[13]]);
+    if command != 0 {
+        return Err(anyhow::anyhow!(
+            "Unexpected command {:#06x} in NEGOTIATE response",
+            command
+        ));
+    }
+
+    // Check the response body
+    let body = &message[HEADER_SIZE..];
+    let structure_size = u16::from_le_bytes([body[0], body[1]]);
+    if structure_size != 65 {
+        return Err(anyhow::anyhow!(
+            "Unexpected NEGOTIATE response size {}",
+            structure_size
+        ));
+    }
+    let dialect = u16::from_le_bytes([body[4], body[5]]);
+    if !DIALECTS.contains(&dialect) {
+        return Err(anyhow::anyhow!(
+            "Server selected unsupported dialect {:#06x}",
+            dialect
+        ));
+    }
+    if dialect == DIALECT_311 && u16::from_le_bytes([body[6], body[7]]) == 0 {
+        return Err(anyhow::anyhow!(
+            "Server selected SMB 3.1.1 without negotiate contexts"
+        ));
+    }
+    let mut guid = [0u8; 16];
+    guid.copy_from_slice(&body[8..24]);
+
+    Ok(Negotiated {
+        dialect,
+        guid: ServerGuid(guid),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The server GUID used in the response fixtures
+    const GUID: [u8; 16] = [
+        0x78, 0x56, 0x34, 0x12, 0x34, 0x12, 0x78, 0x56, 0x9a, 0xbc, 0xde, 0xf0, 0x12, 0x34, 0x56,
+        0x78,
+    ];
+
+    /// A successful NEGOTIATE response selecting SMB 3.0.2
+    fn response() -> Vec<u8> {
+        let mut message = vec![0u8; HEADER_SIZE + 64];
+        message[..4].copy_from_slice(b"\xfeSMB");
+        message[4..6].copy_from_slice(&64u16.to_le_bytes()); // StructureSize
+        message[HEADER_SIZE..HEADER_SIZE + 2].copy_from_slice(&65u16.to_le_bytes()); // StructureSize
+        message[HEADER_SIZE + 4..HEADER_SIZE + 6].copy_from_slice(&0x0302u16.to_le_bytes()); // DialectRevision
+        message[HEADER_SIZE + 8..HEADER_SIZE + 24].copy_from_slice(&GUID); // ServerGuid
+        message
+    }
+
+    #[test]
+    fn builds_negotiate_requests() {
+        let request = negotiate_request();
+
+        // The NetBIOS header gives the length of the SMB2 message
+        assert_eq!(request[0], 0);
+        assert_eq!(
+            u32::from_be_bytes([0, request[1], request[2], request[3]]) as usize,
+            request.len() - 4
+        );
+        let message = &request[4..];
+        assert_eq!(&message[..4], b"\xfeSMB");
+        assert_eq!(&message[12..14], &[0, 0]); // Command (NEGOTIATE)
+
+        // Every dialect is offered, followed by the aligned preauth context
+        let body = &message[HEADER_SIZE..];
+        assert_eq!(&body[..4], &[36, 0, 5, 0]);
+        assert_eq!(
+            &body[36..46],
+            &[0x02, 0x02, 0x10, 0x02, 0x00, 0x03, 0x02, 0x03, 0x11, 0x03]
+        );
+        assert_eq!(&body[28..32], &112u32.to_le_bytes());
+        assert_eq!(&message[112..116], &[1, 0, 38, 0]);
+        assert_eq!(message.len(), 112 + 8 + 38);
+
+        // The client GUID and salt are different every time
+        let other = negotiate_request();
+        assert_eq!(other.len(), request.len());
+        assert_ne!(
+            other[4 + HEADER_SIZE + 12..][..16],
+            request[4 + HEADER_SIZE + 12..][..16]
+        );
+        assert_ne!(other[other.len() - 32..], request[request.len() - 32..]);
+    }
+
+    #[test]
+    fn parses_negotiate_responses() {
+        let negotiated = parse_negotiate_response(&response()).unwrap();
+        assert_eq!(negotiated.dialect, 0x0302);
+        assert_eq!(
+            negotiated.guid.to_string(),
+            "12345678-1234-5678-9abc-def012345678"
+        );
+    }
+
+    #[test]
+    fn requires_contexts_with_smb_311() {
+        let mut message = response();
+        message[HEADER_SIZE + 4..HEADER_SIZE + 6].copy_from_slice(&0x0311u16.to_le_bytes());
+        assert!(parse_negotiate_response(&message).is_err());
+
+        message[HEADER_SIZE + 6..HEADER_SIZE + 8].copy_from_slice(&1u16.to_le_bytes()); // NegotiateContextCount
+        assert_eq!(parse_negotiate_response(&message).unwrap().dialect, 0x0311);
+    }
+
+    #[test]
+    fn rejects_bad_negotiate_responses() {
+        let error = |message: &[u8]| parse_negotiate_response(message).unwrap_err().to_string();
+
+        // Too short to hold the response body
+        assert_eq!(
+            error(&response()[..HEADER_SIZE + 10]),
+            "NEGOTIATE response too short (74 bytes)"
+        );
+
+        // Not SMB2
+        let mut message = response();
+        message[..4].copy_from_slice(b"\xffSMB");
+        assert_eq!(error(&message), "Response is not an SMB2 message");
+
+        // Refused by the server
+        let mut message = response();
+        message[8..12].copy_from_slice(&0xc000_0022u32.to_le_bytes());
+        assert_eq!(error(&message), "NEGOTIATE failed with status 0xc0000022");
+
+        // A dialect that was never offered
+        let mut message = response();
+        message[HEADER_SIZE + 4..HEADER_SIZE + 6].copy_from_slice(&0x02ffu16.to_le_bytes());
+        assert_eq!(
+            error(&message),
+            "Server selected unsupported dialect 0x02ff"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::{fmt, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// A snapshot of what the daemon knows about every server and share
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    /// Whether probing and remounting are paused on request
+    pub paused: bool,
+    /// When a pause ends by itself (RFC3339)
+    pub paused_until: Option<String>,
+    /// The monitored servers
+    pub servers: Vec<ServerStatus>,
+}
+
+/// What the daemon knows about a server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerStatus {
+    /// The configured host name
+    pub host: String,
+    /// Whether the server answered the last reachability probe
+    pub reachable: bool,
+    /// The host the server was last reached through, which differs from `host` after failover
+    pub reached_through: String,
+    /// The address the server was last reached at
+    pub address: Option<String>,
+    /// The GUID the server reported in its last SMB2 NEGOTIATE response
+    pub server_guid: Option<String>,
+    /// The server's shares
+    pub shares: Vec<ShareStatus>,
+}
+
+/// What the daemon knows about a share
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShareStatus {
+    /// The name of the share on the server
+    pub name: String,
+    /// The local directory the share is mounted at
+    pub mount_point: PathBuf,
+    /// The state of the share (e.g., "mounted" or "failed")
+    pub state: String,
+    /// The number of consecutive failed mount attempts
+    pub failed_attempts: u32,
+    /// When the daemon last mounted the share (RFC3339)
+    pub last_mounted: Option<String>,
+    /// The last error mounting the share
+    pub last_error: Option<String>,
+    /// When mounting the share is next retried (RFC3339)
+    pub next_retry: Option<String>,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Say up front that nothing is being checked
+        match &self.paused_until {
+            Some(until) => writeln!(f, "Paused until {}", until)?,
+            None if self.paused => writeln!(f, "Paused until resumed")?,
+            None => {}
+        }
+
+        for server in &self.servers {
+            // One line for the server
+            write!(
+                f,
+                "{}: {}",
+                server.host,
+                if server.reachable { "up" } else { "down" }
+            )?;
+            if server.reached_through != server.host {
+                write!(f, ", via {}", server.reached_through)?;
+            }
+            if let Some(address) = &server.address {
+                write!(f, ", address {}", address)?;
+            }
+            if let Some(guid) = &server.server_guid {
+                write!(f, ", server {}", guid)?;
+            }
+            writeln!(f)?;
+
+            // One line for each share
+            for share in &server.shares {
+                write!(
+                    f,
+                    "  {} at {}: {}",
+                    share.name,
+                    share.mount_point.display(),
+                    share.state
+                )?;
+                match share.failed_attempts {
+                    0 => {}
+                    1 => write!(f, ", 1 failed attempt")?,
+                    attempts => write!(f, ", {} failed attempts", attempts)?,
+                }
+                if let Some(last_mounted) = &share.last_mounted {
+                    write!(f, ", last mounted {}", last_mounted)?;
+                }
+                if let Some(next_retry) = &share.next_retry {
+                    write!(f, ", next retry {}", next_retry)?;
+                }
+                if let Some(last_error) = &share.last_error {
+                    write!(f, ", last error: {}", last_error)?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
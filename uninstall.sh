@@ -33,7 +33,7 @@ if [[ -e "${INSTALL_BIN}" ]]; then
     rm -f "${INSTALL_BIN}"
 fi
 
-for log_file in "${LOG_OUT}" "${LOG_ERR}"; do
+for log_file in "${LOG_OUT}" "${LOG_OUT}".* "${LOG_ERR}"; do
     if [[ -f "${log_file}" ]]; then
         echo "Removing log file ${log_file}..."
         rm -f "${log_file}"